@@ -0,0 +1,75 @@
+// Parse throughput over the fixture corpus, per worker and in total.
+//
+// Run with `cargo bench --bench parser`. Each worker parses the whole corpus
+// BENCH_ITERATIONS times (default 20) on its own thread, like articlewatch workers
+// sharing the blocking pool.
+
+use libtater::parser::profile::{default_profile, CompiledProfile};
+use libtater::parser::snapshot::{
+    corpus_pages, fixture_kind, FixtureKind, FIXTURES_DIR, SNAPSHOT_DATES,
+};
+use libtater::parser::{parse_comments_page, parse_page};
+use std::fs;
+use std::path::Path;
+use std::sync::Arc;
+use std::thread;
+use std::time::Instant;
+
+fn main() -> anyhow::Result<()> {
+    let iterations: usize = std::env::var("BENCH_ITERATIONS")
+        .ok()
+        .and_then(|n| n.parse().ok())
+        .unwrap_or(20);
+    let pages: Arc<Vec<_>> = Arc::new(
+        corpus_pages(Path::new(FIXTURES_DIR))?
+            .iter()
+            .map(|path| Ok((fs::read_to_string(path)?, fixture_kind(path))))
+            .collect::<std::io::Result<_>>()?,
+    );
+    let bytes: usize = pages.iter().map(|(page, _)| page.len()).sum();
+    let profiles: Arc<[CompiledProfile]> = vec![default_profile().clone()].into();
+    println!(
+        "{} pages ({} KiB), {iterations} iterations per worker",
+        pages.len(),
+        bytes / 1024
+    );
+
+    let max_workers = thread::available_parallelism().map_or(1, |n| n.get());
+    let mut workers = 1;
+    while workers <= max_workers {
+        let start = Instant::now();
+        let handles: Vec<_> = (0..workers)
+            .map(|_| {
+                let pages = pages.clone();
+                let profiles = profiles.clone();
+                thread::spawn(move || {
+                    for _ in 0..iterations {
+                        for (page, kind) in pages.iter() {
+                            match kind {
+                                // Pages that do not parse still cost the same to read.
+                                FixtureKind::Page(kind) => {
+                                    let _ = parse_page(page, *kind, &profiles, &SNAPSHOT_DATES);
+                                }
+                                FixtureKind::CommentsPage => {
+                                    parse_comments_page(page, &profiles[0], &SNAPSHOT_DATES);
+                                }
+                            }
+                        }
+                    }
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().expect("benchmark worker panicked");
+        }
+        let elapsed = start.elapsed().as_secs_f64();
+        let parsed = (workers * iterations * pages.len()) as f64;
+        println!(
+            "{workers:>3} workers: {:>8.1} pages/s total, {:>8.1} pages/s per worker",
+            parsed / elapsed,
+            parsed / elapsed / workers as f64
+        );
+        workers *= 2;
+    }
+    Ok(())
+}
@@ -0,0 +1,2 @@
+-- Problems with individual comments that were skipped while checking an article
+ALTER TABLE article_queue ADD COLUMN warning_msg TEXT;
@@ -0,0 +1,5 @@
+-- Keep every comment thread, not just the unanswered ones
+ALTER TABLE comment ADD COLUMN answered BOOLEAN NOT NULL DEFAULT FALSE;
+ALTER TABLE comment_replies ADD COLUMN author_id BIGINT REFERENCES wa_user(id) ON DELETE SET NULL;
+CREATE INDEX comment_replies_parent ON comment_replies(parent);
+CREATE INDEX comment_replies_article_id_user_id ON comment_replies(article_id, user_id);
@@ -0,0 +1,3 @@
+-- Keep the formatted comment body next to the plain text one
+ALTER TABLE comment ADD COLUMN content_html TEXT NOT NULL DEFAULT '';
+ALTER TABLE comment_replies ADD COLUMN content_html TEXT NOT NULL DEFAULT '';
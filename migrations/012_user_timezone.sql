@@ -0,0 +1,2 @@
+-- The timezone WorldAnvil renders comment dates in for this user, if known
+ALTER TABLE commentater_user ADD COLUMN timezone TEXT;
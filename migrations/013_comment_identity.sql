@@ -0,0 +1,73 @@
+-- Comments are synced by a stable identity instead of being replaced on every check.
+-- Existing comments are given the identity the parser gives comments without an anchor, so the
+-- next check updates them in place and their stars and deletions are kept:
+-- the author's WorldAnvil id and the minute the comment was posted, as WorldAnvil rendered it.
+-- Dates were stored as if the minute WorldAnvil rendered was UTC, so reading them back in UTC
+-- gives that minute again.
+-- Comments without a known author cannot be matched to the page. They get a placeholder identity,
+-- are stored again on the next check and the old rows are marked removed.
+ALTER TABLE comment ADD COLUMN identity TEXT;
+ALTER TABLE comment ADD COLUMN removed BOOLEAN NOT NULL DEFAULT FALSE;
+ALTER TABLE comment_replies ADD COLUMN identity TEXT;
+ALTER TABLE comment_replies ADD COLUMN removed BOOLEAN NOT NULL DEFAULT FALSE;
+
+UPDATE comment SET identity = CASE
+    WHEN wa_user.worldanvil_id IS NULL THEN 'legacy:' || comment.id
+    ELSE wa_user.worldanvil_id || '@' || to_char(
+        comment.date AT TIME ZONE 'UTC',
+        'YYYY-MM-DD"T"HH24:MI'
+    )
+END
+FROM comment AS c
+LEFT JOIN wa_user ON wa_user.id = c.author_id
+WHERE c.id = comment.id;
+
+UPDATE comment_replies SET identity = CASE
+    WHEN wa_user.worldanvil_id IS NULL THEN 'legacy-reply:' || comment_replies.id
+    ELSE wa_user.worldanvil_id || '@' || to_char(
+        comment_replies.date AT TIME ZONE 'UTC',
+        'YYYY-MM-DD"T"HH24:MI'
+    )
+END
+FROM comment_replies AS c
+LEFT JOIN wa_user ON wa_user.id = c.author_id
+WHERE c.id = comment_replies.id;
+
+-- Like the parser, tell comments by the same author in the same minute apart by their content,
+-- using the first 8 hex digits of the sha256 of the text.
+CREATE TEMPORARY TABLE repeated_identity AS
+SELECT article_id, identity
+FROM (
+    SELECT article_id, identity FROM comment
+    UNION ALL
+    SELECT article_id, identity FROM comment_replies
+) AS identities
+GROUP BY article_id, identity
+HAVING COUNT(*) > 1;
+
+UPDATE comment
+SET identity = identity || '#' || substr(encode(sha256(convert_to(content, 'UTF8')), 'hex'), 1, 8)
+WHERE (article_id, identity) IN (SELECT article_id, identity FROM repeated_identity);
+UPDATE comment_replies
+SET identity = identity || '#' || substr(encode(sha256(convert_to(content, 'UTF8')), 'hex'), 1, 8)
+WHERE (article_id, identity) IN (SELECT article_id, identity FROM repeated_identity);
+DROP TABLE repeated_identity;
+
+-- Comments that are identical in every way are numbered by age.
+UPDATE comment SET identity = comment.identity || '-' || numbered.n
+FROM (
+    SELECT id, ROW_NUMBER() OVER (PARTITION BY article_id, identity ORDER BY id) AS n
+    FROM comment
+) AS numbered
+WHERE numbered.id = comment.id AND numbered.n > 1;
+UPDATE comment_replies SET identity = comment_replies.identity || '-' || numbered.n
+FROM (
+    SELECT id, ROW_NUMBER() OVER (PARTITION BY article_id, identity ORDER BY id) AS n
+    FROM comment_replies
+) AS numbered
+WHERE numbered.id = comment_replies.id AND numbered.n > 1;
+
+ALTER TABLE comment ALTER COLUMN identity SET NOT NULL;
+ALTER TABLE comment_replies ALTER COLUMN identity SET NOT NULL;
+CREATE UNIQUE INDEX comment_article_id_identity ON comment(article_id, identity);
+CREATE UNIQUE INDEX comment_replies_article_id_identity ON comment_replies(article_id, identity);
@@ -0,0 +1,8 @@
+-- Article metadata read from the article page.
+ALTER TABLE article_content ADD COLUMN author TEXT;
+ALTER TABLE article_content ADD COLUMN word_count INTEGER;
+ALTER TABLE article_content ADD COLUMN likes INTEGER;
+ALTER TABLE article_content ADD COLUMN views INTEGER;
+ALTER TABLE article_content ADD COLUMN tags TEXT[] NOT NULL DEFAULT '{}';
+ALTER TABLE article_content ADD COLUMN article_type TEXT;
+ALTER TABLE article_content ADD COLUMN comments_disabled BOOLEAN NOT NULL DEFAULT FALSE;
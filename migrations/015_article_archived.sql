@@ -0,0 +1,2 @@
+-- Articles that were deleted on WorldAnvil are archived instead of being checked again.
+ALTER TABLE article ADD COLUMN archived BOOLEAN NOT NULL DEFAULT FALSE;
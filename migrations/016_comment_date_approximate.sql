@@ -0,0 +1,4 @@
+-- Comments first seen with a relative date such as "2 hours ago" only have an estimated date.
+-- It is kept until the page shows the full date.
+ALTER TABLE comment ADD COLUMN date_approximate BOOLEAN NOT NULL DEFAULT FALSE;
+ALTER TABLE comment_replies ADD COLUMN date_approximate BOOLEAN NOT NULL DEFAULT FALSE;
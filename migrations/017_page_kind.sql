@@ -0,0 +1,8 @@
+-- Articles are one kind of commentable page. The world homepage, timelines, maps and
+-- manuscripts can be commented on too, and are tracked in the same table.
+ALTER TABLE article ADD COLUMN kind TEXT NOT NULL DEFAULT 'article'
+    CHECK (kind IN ('article', 'world', 'timeline', 'map', 'manuscript'));
+-- Link to the world homepage.
+ALTER TABLE world ADD COLUMN url TEXT;
+-- Only some kinds of page show their WorldAnvil id, the others store NULL.
+ALTER TABLE article_content ALTER COLUMN worldanvil_id DROP NOT NULL;
@@ -0,0 +1,7 @@
+-- Mentions, links and questions found in comment bodies, used to flag comments that need attention.
+ALTER TABLE comment ADD COLUMN mentions TEXT[] NOT NULL DEFAULT '{}';
+ALTER TABLE comment ADD COLUMN links TEXT[] NOT NULL DEFAULT '{}';
+ALTER TABLE comment ADD COLUMN question BOOLEAN NOT NULL DEFAULT FALSE;
+ALTER TABLE comment_replies ADD COLUMN mentions TEXT[] NOT NULL DEFAULT '{}';
+ALTER TABLE comment_replies ADD COLUMN links TEXT[] NOT NULL DEFAULT '{}';
+ALTER TABLE comment_replies ADD COLUMN question BOOLEAN NOT NULL DEFAULT FALSE;
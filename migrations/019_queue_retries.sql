@@ -0,0 +1,7 @@
+-- Tasks that fail with a transient error are retried with a backoff.
+-- Tasks that keep failing are given up on and kept as dead letters until retried by hand.
+ALTER TABLE article_queue ADD COLUMN attempts INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE article_queue ADD COLUMN last_error TEXT;
+ALTER TABLE article_queue ADD COLUMN next_attempt_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW();
+ALTER TABLE article_queue ADD COLUMN dead BOOLEAN NOT NULL DEFAULT FALSE;
+CREATE INDEX article_queue_next_attempt_at ON article_queue(next_attempt_at) WHERE NOT done;
@@ -0,0 +1,6 @@
+-- When WorldAnvil says the page was last edited, used with comment dates to decide how often
+-- the page is rechecked automatically.
+ALTER TABLE article ADD COLUMN updated_at TIMESTAMP WITH TIME ZONE;
+-- The latest comment of every article is looked up when scheduling rechecks.
+CREATE INDEX comment_article_id_date ON comment(article_id, date);
+CREATE INDEX comment_replies_article_id_date ON comment_replies(article_id, date);
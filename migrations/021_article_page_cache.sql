@@ -0,0 +1,5 @@
+-- Validators of the last fetch of the article page, sent back so unchanged pages are not
+-- downloaded again, and a hash of its comments so unchanged comments are not stored again.
+ALTER TABLE article ADD COLUMN etag TEXT;
+ALTER TABLE article ADD COLUMN last_modified TEXT;
+ALTER TABLE article ADD COLUMN comments_hash TEXT;
@@ -0,0 +1,10 @@
+-- Tasks asked for by hand go before scheduled rechecks, which go before bulk queueing.
+-- 0 is bulk, 1 is scheduled and 2 is interactive.
+ALTER TABLE article_queue ADD COLUMN priority SMALLINT NOT NULL DEFAULT 0;
+-- An article has at most one pending task, which enqueueing it again reuses.
+DELETE FROM article_queue duplicate
+USING article_queue original
+WHERE NOT duplicate.done AND NOT original.done
+    AND duplicate.article_id = original.article_id AND duplicate.id > original.id;
+CREATE UNIQUE INDEX article_queue_pending_article ON article_queue(article_id) WHERE NOT done;
+CREATE INDEX article_queue_user_priority ON article_queue(user_id, priority DESC, id) WHERE NOT done;
@@ -0,0 +1,10 @@
+-- Per-user overrides of the queue settings. Where they are NULL the defaults from the
+-- environment apply.
+ALTER TABLE user_queue ADD COLUMN min_interval_secs DOUBLE PRECISION CHECK (min_interval_secs >= 0);
+ALTER TABLE user_queue ADD COLUMN max_pending BIGINT CHECK (max_pending >= 0);
+ALTER TABLE user_queue ADD COLUMN daily_budget INTEGER CHECK (daily_budget >= 0);
+-- Users with a higher weight get their turn more often, e.g. during an event in their world.
+ALTER TABLE user_queue ADD COLUMN weight DOUBLE PRECISION NOT NULL DEFAULT 1 CHECK (weight > 0);
+-- How many checks were made for the user on budget_day.
+ALTER TABLE user_queue ADD COLUMN budget_day DATE;
+ALTER TABLE user_queue ADD COLUMN checks_today INTEGER NOT NULL DEFAULT 0;
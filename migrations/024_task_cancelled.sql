@@ -0,0 +1,3 @@
+-- Tasks the user cancelled before they were worked on. They count as done, so nothing picks
+-- them up, but they were never checked.
+ALTER TABLE article_queue ADD COLUMN cancelled BOOLEAN NOT NULL DEFAULT FALSE;
@@ -0,0 +1,17 @@
+-- When a task was queued, so that old history can be pruned.
+-- Nothing records when existing tasks were queued, so they count as queued now, when this
+-- migration runs. They are pruned once they are that old.
+ALTER TABLE article_queue ADD COLUMN queued_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW();
+-- Finding the latest task of an article.
+CREATE INDEX article_queue_article_id ON article_queue(article_id, id);
+
+-- What the tasks pruned from article_queue amounted to, per article.
+CREATE TABLE article_queue_history (
+    article_id BIGINT PRIMARY KEY REFERENCES article(id) ON DELETE CASCADE,
+    user_id BIGINT NOT NULL REFERENCES commentater_user(id) ON DELETE CASCADE,
+    entries BIGINT NOT NULL DEFAULT 0,
+    -- Tasks that are done, including those that ended in an error or were cancelled.
+    done_count BIGINT NOT NULL DEFAULT 0,
+    errored_count BIGINT NOT NULL DEFAULT 0,
+    cancelled_count BIGINT NOT NULL DEFAULT 0
+);
@@ -1,11 +1,67 @@
-use crate::db::article::{get_article, set_article_checked_time, update_article_content};
-use crate::db::comments::{delete_comments, insert_comments};
+use crate::dateutil::resolve_timezone;
+use crate::db::article::{
+    archive_article, get_article, set_article_checked_time, set_article_page_cache,
+    set_article_url, update_article_content,
+};
+use crate::db::comments::{
+    mark_removed_comments, rename_comment_identities, upsert_comments, upsert_replies,
+};
 use crate::db::query::update_wa_users;
-use crate::db::queue::{complete_task, get_next_task, get_next_user, update_user_queue};
-use crate::db::schema::{ArticleQueueEntry, CommentInsert};
-use crate::parser::{get_page, parse_page, ParseError, RootComment};
+use crate::db::queue::{
+    complete_task, fail_task_attempt, get_next_task, get_next_user, notify_queue, postpone_task,
+    set_task_warnings, update_user_queue, user_has_ready_task, QueueDefaults,
+};
+use crate::db::schema::{ArticleQueueEntry, PageKind};
+use crate::db::user::get_user;
+use crate::parser::date::DateContext;
+use crate::parser::profile::CompiledProfile;
+use crate::parser::state::{classify_comments_page, classify_page, PageState};
+use crate::parser::{get_page, get_page_if_changed, Article, PageValidators, ParseError};
+use chrono_tz::Tz;
+use dotenv::var as envvar;
+use sha2::{Digest, Sha256};
 use sqlx::{Acquire, Postgres};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use std::time::Duration;
+use url::Url;
+
+/// The most pages of comments that are fetched for one article.
+fn comment_page_limit() -> usize {
+    envvar("COMMENT_PAGE_LIMIT")
+        .ok()
+        .and_then(|limit| limit.parse().ok())
+        .unwrap_or(10)
+}
+
+/// How often a task that fails with an unexpected error is tried before giving up on it.
+fn task_max_attempts() -> i32 {
+    envvar("TASK_MAX_ATTEMPTS")
+        .ok()
+        .and_then(|attempts| attempts.parse().ok())
+        .unwrap_or(5)
+}
+
+/// How long to wait before the next attempt at a task, doubling with every failed attempt
+/// from TASK_RETRY_BASE_SECS up to TASK_RETRY_MAX_SECS.
+fn retry_delay(attempts: i32) -> Duration {
+    let secs = |name, default| {
+        envvar(name)
+            .ok()
+            .and_then(|secs| secs.parse().ok())
+            .unwrap_or(default)
+    };
+    backoff(
+        attempts,
+        Duration::from_secs(secs("TASK_RETRY_BASE_SECS", 30)),
+        Duration::from_secs(secs("TASK_RETRY_MAX_SECS", 3600)),
+    )
+}
+
+fn backoff(attempts: i32, base: Duration, max: Duration) -> Duration {
+    let doublings = attempts.saturating_sub(1).clamp(0, 31) as u32;
+    base.saturating_mul(2u32.saturating_pow(doublings)).min(max)
+}
 
 pub struct TaskError {
     pub error: anyhow::Error,
@@ -21,6 +77,8 @@ pub enum TaskOutcome {
     NoUser,
     NoTasks,
     Error(TaskError),
+    /// WorldAnvil is down for maintenance, the task was postponed.
+    Maintenance,
 }
 
 impl ParseError {
@@ -36,18 +94,55 @@ impl ParseError {
     }
 }
 
-/// Find comment indices which have no replies
-fn find_comments_without_replies(comments: &[RootComment]) -> Vec<usize> {
-    comments
-        .iter()
-        .enumerate()
-        .filter_map(|(i, comment)| {
-            if comment.replies.is_empty() {
-                Some(i)
-            } else {
-                None
+/// A page that cannot be read, and why.
+fn unreadable_page(message: &str, user_queue_id: i64, task_id: i64) -> TaskOutcome {
+    TaskOutcome::Error(TaskError {
+        error: anyhow::anyhow!("{message}"),
+        unhandled: false,
+        message: message.to_string(),
+        user_queue_id,
+        task_id,
+    })
+}
+
+/// Download a page unless it is unchanged since it had `validators`, and work out what it is,
+/// parsing it on the blocking pool. Also returns the validators the page has now.
+async fn fetch_page_state(
+    url: &str,
+    validators: &PageValidators,
+    kind: PageKind,
+    profiles: &Arc<[CompiledProfile]>,
+    timezone: Tz,
+) -> anyhow::Result<(Result<PageState, ParseError>, PageValidators)> {
+    let page = get_page_if_changed(url, validators).await?;
+    let validators = page.validators.clone();
+    let url = url.to_string();
+    let profiles = profiles.clone();
+    let state =
+        tokio::task::spawn_blocking(move || classify_page(&page, &url, kind, &profiles, timezone))
+            .await?;
+    Ok((state, validators))
+}
+
+/// A fingerprint of the comments read from an article, to tell whether they changed since the
+/// last check. Only the identity and text of the comments count, relative dates are read
+/// differently on every check.
+fn comments_hash(article: &Article) -> String {
+    let mut hasher = Sha256::new();
+    for thread in &article.comments {
+        for (kind, comment) in std::iter::once(("comment", &thread.comment))
+            .chain(thread.replies.iter().map(|reply| ("reply", reply)))
+        {
+            for field in [kind, &comment.identity, &comment.content] {
+                hasher.update(field.as_bytes());
+                hasher.update([0]);
             }
-        })
+        }
+    }
+    hasher
+        .finalize()
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
         .collect()
 }
 
@@ -56,27 +151,130 @@ fn find_comments_without_replies(comments: &[RootComment]) -> Vec<usize> {
 pub async fn update_task_inner(
     task: &ArticleQueueEntry,
     tx: &mut sqlx::Transaction<'_, Postgres>,
+    profiles: &Arc<[CompiledProfile]>,
 ) -> anyhow::Result<TaskOutcome> {
     let ArticleQueueEntry {
         id: task_id,
         user_id,
         article_id,
+        ..
     } = task.clone();
 
     let article = get_article(&mut *tx, article_id, user_id).await?;
+    let kind: PageKind = article.kind.parse()?;
+    // Only some kinds of page carry their id, for the others the registered one is used.
+    let page_worldanvil_id = |parsed: &Article| {
+        parsed
+            .worldanvil_id
+            .clone()
+            .or_else(|| article.worldanvil_id.clone())
+    };
+    // Answered-ness is judged against the owner of the world.
+    let owner = get_user(&mut *tx, &user_id).await?;
+    let fallback_timezone = resolve_timezone(None, owner.timezone.as_deref());
 
-    let page = get_page(&article.url).await?;
-    let parsed = match parse_page(&page) {
-        Ok(article) => article,
+    let previous = PageValidators {
+        etag: article.etag.clone(),
+        last_modified: article.last_modified.clone(),
+    };
+    let (url, (state, validators)) =
+        match fetch_page_state(&article.url, &previous, kind, profiles, fallback_timezone).await? {
+            (Ok(PageState::Redirected { canonical_url }), _) => {
+                log::info!("Article {article_id} moved to {canonical_url}");
+                set_article_url(&mut *tx, article_id, user_id, &canonical_url).await?;
+                let fetched = fetch_page_state(
+                    &canonical_url,
+                    &PageValidators::default(),
+                    kind,
+                    profiles,
+                    fallback_timezone,
+                )
+                .await?;
+                (canonical_url, fetched)
+            }
+            fetched => (article.url.clone(), fetched),
+        };
+    let mut parsed = match state {
+        Ok(PageState::Ok(parsed)) => parsed,
+        Ok(PageState::CommentsDisabled(parsed)) => {
+            log::info!("Comments are disabled on article {article_id}");
+            update_article_content(
+                &mut *tx,
+                article_id,
+                page_worldanvil_id(&parsed).as_deref(),
+                &parsed.title,
+                &parsed.metadata,
+            )
+            .await?;
+            return Ok(TaskOutcome::Completed);
+        }
+        Ok(PageState::NotFound) => {
+            log::info!("Article {article_id} no longer exists, archiving it");
+            archive_article(&mut *tx, article_id, user_id).await?;
+            let warning = "the article no longer exists and was archived".to_string();
+            set_task_warnings(task_id, &[warning], tx).await?;
+            return Ok(TaskOutcome::Completed);
+        }
+        Ok(PageState::Maintenance) => return Ok(TaskOutcome::Maintenance),
+        Ok(PageState::NotModified) => {
+            log::info!("Article {article_id} is unchanged since the last check");
+            return Ok(TaskOutcome::Completed);
+        }
+        Ok(PageState::Private) => {
+            return Ok(unreadable_page("the article is private", user_id, task_id))
+        }
+        Ok(PageState::SubscriberGated) => {
+            let message = "the article is only visible to subscribers";
+            return Ok(unreadable_page(message, user_id, task_id));
+        }
+        Ok(PageState::Redirected { canonical_url }) => {
+            let message = format!("the article moved again, to {canonical_url}");
+            return Ok(unreadable_page(&message, user_id, task_id));
+        }
         Err(e) => return Ok(e.into_parse_error(user_id, task_id)),
     };
-    update_article_content(&mut *tx, article_id, &parsed.worldanvil_id, &parsed.title).await?;
-    // Clean old comments
-    delete_comments(&mut *tx, article_id, user_id).await?;
+    log::debug!(
+        "Parsed article {article_id} with profile {}",
+        parsed.profile
+    );
+    let timezone = resolve_timezone(parsed.timezone, owner.timezone.as_deref());
+    log::debug!("Reading comment dates of article {article_id} as {timezone}");
+    let complete = fetch_comment_pages(&url, &mut parsed, profiles, timezone).await?;
+    update_article_content(
+        &mut *tx,
+        article_id,
+        page_worldanvil_id(&parsed).as_deref(),
+        &parsed.title,
+        &parsed.metadata,
+    )
+    .await?;
+    for warning in &parsed.warnings {
+        log::warn!("Article {article_id}: {warning}");
+    }
+    set_task_warnings(task_id, &parsed.warnings, tx).await?;
+    // Comments can only be known to be unchanged if all of them were read, and a page that
+    // was not read through must not be skipped as unmodified next time either.
+    let (hash, validators) = if complete {
+        (Some(comments_hash(&parsed)), validators)
+    } else {
+        (None, PageValidators::default())
+    };
+    set_article_page_cache(
+        &mut *tx,
+        article_id,
+        user_id,
+        validators.etag.as_deref(),
+        validators.last_modified.as_deref(),
+        hash.as_deref(),
+    )
+    .await?;
+    if hash.is_some() && hash == article.comments_hash {
+        log::info!("Comments of article {article_id} are unchanged since the last check");
+        return Ok(TaskOutcome::Completed);
+    }
     let potential_users = parsed
-        .comments
-        .iter()
-        .map(|comment| comment.as_worldanvil_user())
+        .all_comments()
+        .filter_map(|comment| comment.as_worldanvil_user())
         .collect();
     let users = update_wa_users(&mut *tx, potential_users).await?;
     // Turn users into a map from worldanvil id to internal id
@@ -84,47 +282,157 @@ pub async fn update_task_inner(
         .into_iter()
         .filter_map(|(id, wa_id)| wa_id.map(|i| (i, id)))
         .collect();
-    // Transform potential users into insertable comments only if they have no replies
-    let comments: Vec<_> = parsed
+    // Comments without a known author are kept with no author id.
+    let author_id = |wa_id: Option<&str>| {
+        wa_id.and_then(|wa_id| {
+            let internal_id = user_map.get(wa_id).copied();
+            if internal_id.is_none() {
+                log::info!("Could not find internal id for user {wa_id}");
+            }
+            internal_id
+        })
+    };
+    // Comments first seen with a relative date now show their full date.
+    let renames: Vec<_> = parsed
+        .all_comments()
+        .filter_map(|comment| {
+            comment
+                .approximate_identity
+                .as_ref()
+                .filter(|approximate| **approximate != comment.identity)
+                .map(|approximate| (approximate.clone(), comment.identity.clone()))
+        })
+        .collect();
+    rename_comment_identities(&mut *tx, article_id, &renames).await?;
+    // Sync every thread in place, linking the replies to their root comment.
+    let comments = parsed
         .comments
         .iter()
-        .filter_map(
-            |comment| match user_map.get(&comment.author_worldanvil_id) {
-                Some(internal_id) => {
-                    if comment.replies.is_empty() {
-                        Some(CommentInsert {
-                            user_id,
-                            author_id: *internal_id,
-                            article_id,
-                            content: comment.comment.content.clone(),
-                            date: comment.datetime().assume_utc(),
-                        })
-                    } else {
-                        None
-                    }
-                }
-                None => {
-                    log::info!(
-                        "Could not find internal id for user {}",
-                        comment.author_worldanvil_id
-                    );
-                    None
-                }
-            },
-        )
+        .map(|thread| {
+            thread.as_db_comment(
+                user_id,
+                author_id(thread.author_worldanvil_id()),
+                article_id,
+                &owner.worldanvil_id,
+                timezone,
+            )
+        })
         .collect();
-
-    if !comments.is_empty() {
-        let n = comments.len();
-        insert_comments(&mut *tx, article_id, user_id, comments).await?;
-        log::info!("Inserted {n} comments for article {article_id} of user {user_id}")
+    let comment_ids = upsert_comments(&mut *tx, comments).await?;
+    let replies = parsed
+        .comments
+        .iter()
+        .zip(comment_ids)
+        .flat_map(|(thread, comment_id)| {
+            thread.replies.iter().map(move |reply| {
+                let reply = reply.as_db_reply(
+                    user_id,
+                    author_id(reply.author_worldanvil_id.as_deref()),
+                    article_id,
+                    timezone,
+                );
+                (comment_id, reply)
+            })
+        })
+        .collect();
+    upsert_replies(&mut *tx, replies).await?;
+    // Whatever was not seen on the page this time has been removed from it.
+    let comment_identities: Vec<_> = parsed
+        .comments
+        .iter()
+        .map(|thread| thread.comment.identity.clone())
+        .collect();
+    let reply_identities: Vec<_> = parsed
+        .comments
+        .iter()
+        .flat_map(|thread| thread.replies.iter().map(|reply| reply.identity.clone()))
+        .collect();
+    // Comments on pages that were not fetched may well still exist.
+    if complete {
+        mark_removed_comments(
+            &mut *tx,
+            article_id,
+            user_id,
+            &comment_identities,
+            &reply_identities,
+        )
+        .await?;
+    }
+    if !parsed.comments.is_empty() {
+        let n = parsed.comments.len();
+        log::info!("Stored {n} comment threads for article {article_id} of user {user_id}")
     }
     Ok(TaskOutcome::Completed)
 }
 
-pub async fn update_task(mut tx: sqlx::Transaction<'_, Postgres>) -> anyhow::Result<TaskOutcome> {
+/// Follow the "more comments" links of an article, merging every page into it.
+/// Further pages are read with the profile that understood the first one.
+/// Returns whether all pages were read, which they are not if one of them is an error page.
+async fn fetch_comment_pages(
+    url: &str,
+    article: &mut Article,
+    profiles: &Arc<[CompiledProfile]>,
+    timezone: Tz,
+) -> anyhow::Result<bool> {
+    let limit = comment_page_limit();
+    let mut current = Url::parse(url)?;
+    let mut visited = HashSet::from([current.clone()]);
+    while let Some(link) = article.next_page.take() {
+        let next = current.join(&link)?;
+        if !visited.insert(next.clone()) {
+            log::warn!("Comment pages of {url} link back to {next}");
+            break;
+        }
+        if visited.len() > limit {
+            article.warnings.push(format!(
+                "only the first {limit} pages of comments were read"
+            ));
+            return Ok(false);
+        }
+        let page = get_page(next.as_str()).await?;
+        let dates = DateContext {
+            fetched_at: page.fetched_at,
+            timezone,
+        };
+        let profiles = profiles.clone();
+        let profile_index = article.profile_index;
+        let comments = tokio::task::spawn_blocking(move || {
+            classify_comments_page(&page, &profiles[profile_index], &dates)
+        })
+        .await?;
+        let n = visited.len();
+        let mut comments = match comments {
+            Ok(comments) => comments,
+            Err(reason) => {
+                article
+                    .warnings
+                    .push(format!("comments page {n} could not be read: {reason}"));
+                return Ok(false);
+            }
+        };
+        comments.warnings = comments
+            .warnings
+            .into_iter()
+            .map(|warning| format!("comments page {n}: {warning}"))
+            .collect();
+        article.merge(comments);
+        current = next;
+    }
+    Ok(true)
+}
+
+/// Work on the next task, if anyone has one that is ready.
+/// Tasks that find WorldAnvil down for maintenance are tried again after `maintenance_backoff`.
+/// `on_claim` is called once a task has been claimed, before any work on it.
+pub async fn update_task(
+    mut tx: sqlx::Transaction<'_, Postgres>,
+    profiles: &Arc<[CompiledProfile]>,
+    maintenance_backoff: Duration,
+    on_claim: impl FnOnce(),
+) -> anyhow::Result<TaskOutcome> {
     // Lock a valid user
-    let user_queue_entry = get_next_user(&mut tx).await?;
+    let defaults = QueueDefaults::from_env();
+    let user_queue_entry = get_next_user(&defaults, &mut tx).await?;
     let user_queue_entry = match user_queue_entry {
         Some(entry) => entry,
         None => return Ok(TaskOutcome::NoUser),
@@ -134,14 +442,28 @@ pub async fn update_task(mut tx: sqlx::Transaction<'_, Postgres>) -> anyhow::Res
         Some(task) => task,
         None => return Ok(TaskOutcome::NoTasks),
     };
+    on_claim();
     log::info!("Working on {}", task.article_id);
     // Use inner transaction to discard partial updates.
     let mut inner_tx = tx.begin().await?;
-    match update_task_inner(&task, &mut inner_tx).await {
+    match update_task_inner(&task, &mut inner_tx, profiles).await {
         Ok(TaskOutcome::NoTasks | TaskOutcome::NoUser) => {
             // This should never be returned.
             panic!("No tasks returned from inner update task");
         }
+        Ok(TaskOutcome::Maintenance) => {
+            // Leave the task queued for when WorldAnvil is back. Nothing was checked, so the
+            // user's daily budget is left alone.
+            log::warn!(
+                "WorldAnvil is down for maintenance, trying task {} again in {}s",
+                task.id,
+                maintenance_backoff.as_secs()
+            );
+            inner_tx.rollback().await?;
+            postpone_task(task.id, maintenance_backoff, &mut tx).await?;
+            tx.commit().await?;
+            return Ok(TaskOutcome::Maintenance);
+        }
         Ok(TaskOutcome::Error(task_error)) => {
             let TaskError {
                 error,
@@ -168,11 +490,143 @@ pub async fn update_task(mut tx: sqlx::Transaction<'_, Postgres>) -> anyhow::Res
             update_user_queue(&user_queue_entry.id, &mut tx).await?;
         }
         Err(e) => {
-            // Log error and continue
+            // Something unexpected such as a timeout, which may well go away by itself.
             log::error!("{e:?}");
             inner_tx.rollback().await?;
+            let attempts = task.attempts + 1;
+            let delay = retry_delay(attempts);
+            let dead = fail_task_attempt(
+                task.id,
+                &format!("{e:#}"),
+                delay,
+                task_max_attempts(),
+                &mut tx,
+            )
+            .await?;
+            if dead {
+                log::warn!("Giving up on task {} after {attempts} attempts", task.id);
+            } else {
+                log::info!("Retrying task {} in {}s", task.id, delay.as_secs());
+            }
+            update_user_queue(&user_queue_entry.id, &mut tx).await?;
         }
     }
+    // Idle workers may be waiting for this user to be free again. Once their cooldown runs
+    // out, the worker that finished this task finds the user by itself.
+    if user_has_ready_task(&user_queue_entry.user_id, &defaults, &mut tx).await? {
+        notify_queue(&mut tx).await?;
+    }
     tx.commit().await?;
     Ok(TaskOutcome::Completed)
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_backoff() {
+        let base = Duration::from_secs(30);
+        let max = Duration::from_secs(3600);
+        let delays: Vec<_> = (1..=9)
+            .map(|attempts| backoff(attempts, base, max).as_secs())
+            .collect();
+        assert_eq!(delays, [30, 60, 120, 240, 480, 960, 1920, 3600, 3600]);
+        assert_eq!(backoff(i32::MAX, base, max), max);
+    }
+
+    /// A further page of comments that cannot be read leaves the comments incomplete, so the
+    /// ones it showed are not marked removed.
+    #[tokio::test]
+    async fn test_fetch_comment_pages_unavailable() -> anyhow::Result<()> {
+        use crate::parser::parse_page;
+        use crate::parser::profile::default_profile;
+        use crate::parser::snapshot::SNAPSHOT_DATES;
+        use axum::http::StatusCode;
+        use axum::response::Html;
+        use axum::Router;
+
+        let first = std::fs::read_to_string("fixtures/paginated-page-1.htm")?;
+        let profiles: Arc<[CompiledProfile]> = vec![default_profile().clone()].into();
+        let maintenance = r#"<div class="maintenance-page">Back soon</div>"#;
+        for (status, body) in [
+            (StatusCode::SERVICE_UNAVAILABLE, ""),
+            (StatusCode::NOT_FOUND, ""),
+            (StatusCode::OK, maintenance),
+        ] {
+            let app = Router::new().fallback(move || async move { (status, Html(body)) });
+            let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+            let url = format!(
+                "http://{}/w/solaris-nnie/a/chewpaper-material",
+                listener.local_addr()?
+            );
+            let server = tokio::spawn(async move { axum::serve(listener, app).await });
+            let mut article = parse_page(&first, PageKind::Article, &profiles, &SNAPSHOT_DATES)?;
+            let complete = fetch_comment_pages(&url, &mut article, &profiles, Tz::UTC).await?;
+            server.abort();
+            assert!(!complete, "{status}");
+            assert_eq!(article.comments.len(), 2);
+            assert!(
+                article.warnings[0].starts_with("comments page 2 could not be read"),
+                "{:?}",
+                article.warnings
+            );
+        }
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn test_maintenance_keeps_budget(pool: sqlx::PgPool) -> anyhow::Result<()> {
+        use crate::db::queue::{get_user_queue_settings, insert_tasks};
+        use crate::db::schema::TaskPriority;
+        use crate::db::test_queries::add_test_article;
+        use crate::db::user::insert_user_queue;
+        use crate::parser::profile::default_profile;
+        use axum::http::StatusCode;
+        use axum::Router;
+
+        let app = Router::new().fallback(|| async { StatusCode::SERVICE_UNAVAILABLE });
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+        let url = format!(
+            "http://{}/w/solaris-nnie/a/chewpaper-material",
+            listener.local_addr()?
+        );
+        let server = tokio::spawn(async move { axum::serve(listener, app).await });
+        let mut conn = pool.acquire().await?;
+        let (user, _, article_id) = add_test_article(&mut *conn, "reader", &url).await?;
+        insert_user_queue(&mut *conn, &user).await?;
+        insert_tasks(&user, &[article_id], TaskPriority::Interactive, &mut conn).await?;
+
+        let profiles: Arc<[CompiledProfile]> = vec![default_profile().clone()].into();
+        let backoff = Duration::from_secs(300);
+        let outcome = update_task(pool.begin().await?, &profiles, backoff, || {}).await?;
+        server.abort();
+        assert!(matches!(outcome, TaskOutcome::Maintenance));
+        let defaults = QueueDefaults::from_env();
+        let settings = get_user_queue_settings(&user, &defaults, &mut conn).await?;
+        assert_eq!(settings.checks_today, 0);
+        assert_eq!(settings.pending, 1);
+        Ok(())
+    }
+
+    #[test]
+    fn test_comments_hash() {
+        use crate::parser::parse_page;
+        use crate::parser::profile::default_profile;
+        use crate::parser::snapshot::SNAPSHOT_DATES;
+
+        let body = std::fs::read_to_string("fixtures/example-solaris-page.htm").unwrap();
+        let profiles = std::slice::from_ref(default_profile());
+        let parse = |body: &str| parse_page(body, PageKind::Article, profiles, &SNAPSHOT_DATES);
+        let mut article = parse(&body).unwrap();
+        let hash = comments_hash(&article);
+        // Nothing but the comments counts, and of those not their dates.
+        article.title = "Renamed".to_string();
+        article.comments[0].comment.comment_datetime += time::Duration::hours(1);
+        assert_eq!(comments_hash(&article), hash);
+        article.comments[0].comment.content.push_str(" Edited.");
+        assert_ne!(comments_hash(&article), hash);
+        article.comments.pop();
+        assert_ne!(comments_hash(&article), hash);
+    }
+}
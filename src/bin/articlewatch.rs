@@ -1,32 +1,50 @@
 // Runs in the background to do article updates.
 
 use dotenv::dotenv;
-use libtater::article_updater::{update_task, TaskError, TaskOutcome};
+use dotenv::var as envvar;
 use libtater::db::get_connection_options;
+use libtater::parser::profile::{load_profiles, CompiledProfile};
 use libtater::setup_logging;
+use libtater::worker::{run_workers, shutdown_signal, WorkerConfig};
 use sqlx::PgPool;
+use std::sync::Arc;
+use std::time::Duration;
+
+fn setting<T: std::str::FromStr>(name: &str, default: T) -> T {
+    envvar(name)
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(default)
+}
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
     dotenv().ok();
     setup_logging("log/articlewatch.log")?;
     let pool = PgPool::connect_with(get_connection_options()).await?;
-    loop {
-        let tx = pool.begin().await?;
-        match update_task(tx).await? {
-            TaskOutcome::NoTasks => {
-                log::debug!("No tasks!");
-                tokio::time::sleep(tokio::time::Duration::from_secs(1)).await;
-            }
-            TaskOutcome::NoUser => {
-                log::debug!("No user!");
-                tokio::time::sleep(tokio::time::Duration::from_secs(1)).await;
-            }
-            TaskOutcome::Error(task_err) => {
-                let TaskError { error, .. } = task_err;
-                log::error!("{error:?}");
-            }
-            _ => {}
-        }
+    let profiles: Arc<[CompiledProfile]> = load_profiles()?.into();
+    let config = WorkerConfig {
+        // Workers share the page rate limit, so more of them only helps while pages are slow.
+        workers: setting("ARTICLEWATCH_WORKERS", 4).max(1),
+        maintenance_backoff: Duration::from_secs(setting("MAINTENANCE_BACKOFF_SECS", 300)),
+        idle_timeout: Duration::from_secs(setting("ARTICLEWATCH_IDLE_TIMEOUT_SECS", 30)),
+        shutdown_timeout: Duration::from_secs(setting("ARTICLEWATCH_SHUTDOWN_TIMEOUT_SECS", 30)),
+        // Set to 0 to only check articles when asked to.
+        recheck_every: Some(setting("AUTO_RECHECK_EVERY_SECS", 300))
+            .filter(|secs| *secs > 0)
+            .map(Duration::from_secs),
+        // Set to 0 to keep all task history.
+        prune_every: Some(setting("QUEUE_PRUNE_EVERY_SECS", 3600))
+            .filter(|secs| *secs > 0)
+            .map(Duration::from_secs),
+        keep_history: Duration::from_secs(setting("QUEUE_HISTORY_DAYS", 30) * 86400),
+    };
+    for profile in profiles.iter() {
+        log::info!("Using selector profile {}", profile.label());
     }
+    log::info!("Starting {} workers", config.workers);
+    run_workers(&pool, profiles, config, shutdown_signal()).await?;
+    // Closing the pool makes sure transactions of abandoned tasks are rolled back.
+    pool.close().await;
+    Ok(())
 }
@@ -3,7 +3,6 @@
 use dotenv::dotenv;
 use libtater::db::get_connection_options;
 use libtater::db::schema::CommentaterUser;
-use libtater::db::test_queries::add_test_data;
 use libtater::db::world::get_worlds;
 use libtater::req::get_wa_client_builder;
 use libtater::worldanvil_api::world_list_articles;
@@ -39,7 +38,7 @@ WHERE article.url = dupes.url AND article.id <> dupes.id
     // For each user, merge worldanvil ids based on the url
     let users = sqlx::query_as!(
         CommentaterUser,
-        "SELECT id, display_name, api_key, last_seen, worldanvil_id
+        "SELECT id, display_name, api_key, last_seen, worldanvil_id, timezone
         FROM commentater_user
         "
     )
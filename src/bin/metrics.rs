@@ -25,6 +25,7 @@ struct ArticleQueueInfo {
     done_count: i64,
     pending_count: i64,
     errored_count: i64,
+    cancelled_count: i64,
 }
 
 #[tokio::main]
@@ -34,12 +35,18 @@ async fn main() -> anyhow::Result<()> {
     let results = sqlx::query_as!(
         ArticleQueueInfo,
         r#"
+        -- Tasks pruned from the queue still count.
+        WITH pruned AS (SELECT * FROM article_queue_history)
         SELECT
             NOW() as "time!",
-            COUNT(1) as "total_entries!",
-            COUNT(1) FILTER (where done=true) as "done_count!",
+            COUNT(1) + (SELECT COALESCE(SUM(entries), 0) FROM pruned)::bigint as "total_entries!",
+            COUNT(1) FILTER (where done=true)
+                + (SELECT COALESCE(SUM(done_count), 0) FROM pruned)::bigint as "done_count!",
             COUNT(1) FILTER (where done<>true) as "pending_count!",
-            COUNT(1) FILTER (where error=true) as "errored_count!"
+            COUNT(1) FILTER (where error=true)
+                + (SELECT COALESCE(SUM(errored_count), 0) FROM pruned)::bigint as "errored_count!",
+            COUNT(1) FILTER (where cancelled=true)
+                + (SELECT COALESCE(SUM(cancelled_count), 0) FROM pruned)::bigint as "cancelled_count!"
         FROM article_queue;
         "#
     )
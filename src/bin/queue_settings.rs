@@ -0,0 +1,66 @@
+// Show or change the queue settings of a user.
+//
+// Usage: queue_settings <user id> [<min interval secs> <weight> <max pending> <daily budget>]
+// Without settings, the settings in effect for the user are shown.
+// "default" leaves a setting to the defaults from the environment.
+// Weights are per user. Giving a world more checks than the others, e.g. during an event,
+// is not supported: its owner's weight can be raised for the time being instead.
+
+use dotenv::dotenv;
+use libtater::db::get_connection_options;
+use libtater::db::queue::{get_user_queue_settings, set_user_queue_settings, QueueDefaults};
+use sqlx::PgPool;
+use std::str::FromStr;
+use std::time::Duration;
+
+/// A setting, or None for "default".
+fn parse_setting<T: FromStr>(arg: &str) -> anyhow::Result<Option<T>>
+where
+    T::Err: std::error::Error + Send + Sync + 'static,
+{
+    if arg == "default" {
+        return Ok(None);
+    }
+    Ok(Some(arg.parse()?))
+}
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    dotenv().ok();
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let usage = "usage: queue_settings <user id> \
+        [<min interval secs> <weight> <max pending> <daily budget>]";
+    let (user_id, settings) = match args.as_slice() {
+        [user_id] => (user_id, None),
+        [user_id, settings @ ..] if settings.len() == 4 => (user_id, Some(settings)),
+        _ => anyhow::bail!(usage),
+    };
+    let user_id: i64 = user_id.parse()?;
+    let pool = PgPool::connect_with(get_connection_options()).await?;
+    let mut conn = pool.acquire().await?;
+
+    if let Some([min_interval, weight, max_pending, daily_budget]) = settings {
+        let min_interval = parse_setting::<f64>(min_interval)?.map(Duration::from_secs_f64);
+        let weight = parse_setting(weight)?.unwrap_or(1.0);
+        if weight <= 0.0 {
+            anyhow::bail!("the weight must be positive");
+        }
+        let max_pending = parse_setting(max_pending)?;
+        let daily_budget = parse_setting(daily_budget)?;
+        let updated = set_user_queue_settings(
+            &user_id,
+            min_interval,
+            weight,
+            max_pending,
+            daily_budget,
+            &mut conn,
+        )
+        .await?;
+        if !updated {
+            anyhow::bail!("user {user_id} has no queue, they have to log in first");
+        }
+    }
+    let settings = get_user_queue_settings(&user_id, &QueueDefaults::from_env(), &mut conn).await?;
+    println!("{settings:#?}");
+    Ok(())
+}
@@ -1,59 +1,36 @@
-use anyhow;
-use axum::extract::{Path, Request, State};
+use axum::extract::{Path, Query, Request, State};
 use axum::http::Method;
 use axum::response::{IntoResponse, Redirect, Response};
 use axum::routing::post;
 use axum::{response::Html, routing::get, Router, ServiceExt};
 use dotenv::dotenv;
 use libtater::auth::UserState;
+use libtater::dateutil::default_timezone;
 use libtater::db::article::get_articles_and_status;
 use libtater::db::get_connection_options;
-use libtater::db::queue::get_queue_length;
+use libtater::db::queue::{get_queue_length, get_user_queue_settings, QueueDefaults};
 use libtater::db::schema::WorldInsert;
 use libtater::db::user::get_user;
 use libtater::db::world::{get_world, get_worlds, upsert_worlds};
 use libtater::err::AppError;
 use libtater::req::get_wa_client_builder;
 use libtater::routes::article;
+use libtater::routes::article::PriorityFilter;
 use libtater::routes::login::{login_get, login_post};
+use libtater::routes::settings::set_timezone;
 use libtater::setup_logging;
 use libtater::templates::TEMPLATES;
+use libtater::worker::shutdown_signal;
 use libtater::worldanvil_api::get_worlds_for_user;
 use sqlx::PgPool;
 use tera::Context;
 use time::Duration;
-use tokio::task::AbortHandle;
 use tower::Layer;
 use tower_http::normalize_path::NormalizePathLayer;
 use tower_http::services::ServeDir;
 use tower_sessions::{ExpiredDeletion, Expiry, SessionManagerLayer};
 use tower_sessions_sqlx_store::PostgresStore;
 
-async fn shutdown_signal(deletion_task_abort_handle: AbortHandle) {
-    use tokio::signal;
-    let ctrl_c = async {
-        signal::ctrl_c()
-            .await
-            .expect("failed to install Ctrl+C handler");
-    };
-
-    #[cfg(unix)]
-    let terminate = async {
-        signal::unix::signal(signal::unix::SignalKind::terminate())
-            .expect("failed to install signal handler")
-            .recv()
-            .await;
-    };
-
-    #[cfg(not(unix))]
-    let terminate = std::future::pending::<()>();
-
-    tokio::select! {
-        _ = ctrl_c => { deletion_task_abort_handle.abort() },
-        _ = terminate => { deletion_task_abort_handle.abort() },
-    }
-}
-
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
     dotenv().ok();
@@ -88,19 +65,36 @@ async fn main() -> anyhow::Result<()> {
             "/world/{world_id}/article/{article_id}/enqueue",
             post(article::queue_one_article),
         )
+        .route(
+            "/world/{world_id}/article/{article_id}/retry",
+            post(article::retry_article),
+        )
+        .route(
+            "/world/{world_id}/article/{article_id}/cancel",
+            post(article::cancel_article),
+        )
         .route("/session", get(check_session))
         .route(
             "/world/{world_id}/queue_all",
             get(article::queue_all_articles),
         )
+        .route(
+            "/world/{world_id}/cancel_all",
+            post(article::cancel_all_articles),
+        )
         .route("/login", get(login_get).post(login_post))
+        .route("/timezone", post(set_timezone))
         .nest_service("/static", ServeDir::new("static"))
         .with_state(pool)
         .layer(session_layer);
     let app = NormalizePathLayer::trim_trailing_slash().layer(app);
+    let deletion_task_abort_handle = session_deleter.abort_handle();
     let listener = tokio::net::TcpListener::bind("127.0.0.1:8080").await?;
     axum::serve(listener, ServiceExt::<Request>::into_make_service(app))
-        .with_graceful_shutdown(shutdown_signal(session_deleter.abort_handle()))
+        .with_graceful_shutdown(async move {
+            shutdown_signal().await;
+            deletion_task_abort_handle.abort();
+        })
         .await?;
 
     session_deleter.await??;
@@ -116,14 +110,15 @@ async fn list_worlds(
 ) -> Result<Html<String>, AppError> {
     let mut context = Context::new();
     if let Some(user_id) = &user_state.user_id {
+        let user = get_user(&pool, user_id).await?;
         // If we're in the POST method, update the worlds before fetching them.
         if method == Method::POST {
-            let user = get_user(&pool, user_id).await?;
             let client = get_wa_client_builder(&user.api_key).build()?;
             let worlds = get_worlds_for_user(&client, &user.worldanvil_id).await?;
             let worlds = worlds
                 .into_iter()
                 .map(|world| WorldInsert {
+                    url: world.homepage(),
                     worldanvil_id: world.id,
                     name: world.title,
                 })
@@ -132,6 +127,15 @@ async fn list_worlds(
         }
         let worlds = get_worlds(&pool, user_id).await?;
         context.insert("worlds", &worlds);
+        context.insert("timezone", &user.timezone.unwrap_or_default());
+        context.insert("default_timezone", default_timezone().name());
+        let queue_settings = get_user_queue_settings(
+            user_id,
+            &QueueDefaults::from_env(),
+            &mut *pool.acquire().await?,
+        )
+        .await?;
+        context.insert("queue_settings", &queue_settings);
     }
     user_state.insert_context(&mut context);
     let queue_length = get_queue_length(&mut *pool.acquire().await?).await?;
@@ -145,6 +149,7 @@ async fn list_worlds(
 async fn list_articles(
     State(pool): State<PgPool>,
     Path(world_id): Path<i64>,
+    Query(filter): Query<PriorityFilter>,
     user_state: UserState,
 ) -> Result<Response, AppError> {
     if user_state.user_id.is_none() {
@@ -160,9 +165,15 @@ async fn list_articles(
         let world = get_world(&pool, user_id, &world_id)
             .await
             .map_err(AppError::from_sql("world", &world_id))?;
-        let articles = get_articles_and_status(user_id, &world_id, &pool).await?;
+        let mut articles =
+            get_articles_and_status(user_id, &world_id, user_state.user_name.as_deref(), &pool)
+                .await?;
+        if filter.priority {
+            articles.retain(|article| article.priority_comments > 0);
+        }
         context.insert("world", &world);
         context.insert("articles", &articles);
+        context.insert("priority_only", &filter.priority);
     }
 
     let html = TEMPLATES.render("list_articles.html", &context)?;
@@ -0,0 +1,53 @@
+// Capture a page into the parser fixtures and show how its parse differs from the saved snapshot.
+//
+// Usage: snapshot <name> [url] [--check]
+// With a url the page is downloaded to fixtures/<name>.htm first.
+// The snapshot fixtures/<name>.json is rewritten unless --check is given.
+// Name pages that are not articles after their kind, e.g. world-homepage or map-caloris.
+// Further pages of an article's comments are named after the first, e.g. paginated-page-2.
+
+use dotenv::dotenv;
+use libtater::parser::get_page;
+use libtater::parser::profile::load_profiles;
+use libtater::parser::snapshot::{
+    diff_lines, fixture_kind, render_snapshot, snapshot_path, FIXTURES_DIR,
+};
+use std::fs;
+use std::path::Path;
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    dotenv().ok();
+    let mut args: Vec<String> = std::env::args().skip(1).collect();
+    let check = args.iter().any(|arg| arg == "--check");
+    args.retain(|arg| arg != "--check");
+    let (name, url) = match args.as_slice() {
+        [name] => (name, None),
+        [name, url] => (name, Some(url)),
+        _ => anyhow::bail!("usage: snapshot <name> [url] [--check]"),
+    };
+
+    let page_path = Path::new(FIXTURES_DIR).join(format!("{name}.htm"));
+    if let Some(url) = url {
+        let page = get_page(url).await?;
+        fs::write(&page_path, page.body)?;
+        println!("Saved {url} to {}", page_path.display());
+    }
+    let body = fs::read_to_string(&page_path)?;
+    let rendered = render_snapshot(&body, fixture_kind(&page_path), &load_profiles()?);
+
+    let snapshot_path = snapshot_path(&page_path);
+    let previous = fs::read_to_string(&snapshot_path).unwrap_or_default();
+    let diff = diff_lines(&previous, &rendered);
+    if diff.is_empty() {
+        println!("{} is unchanged", snapshot_path.display());
+        return Ok(());
+    }
+    print!("{diff}");
+    if check {
+        anyhow::bail!("{} is out of date", snapshot_path.display());
+    }
+    fs::write(&snapshot_path, rendered)?;
+    println!("Wrote {}", snapshot_path.display());
+    Ok(())
+}
@@ -1,7 +1,10 @@
+use chrono::{NaiveDate, Offset, TimeZone};
+use chrono_tz::Tz;
+use dotenv::var as envvar;
 use lazy_static::lazy_static;
 use serde::Serializer;
 use time::format_description::OwnedFormatItem;
-use time::{format_description, OffsetDateTime};
+use time::{format_description, OffsetDateTime, PrimitiveDateTime, UtcOffset};
 
 lazy_static! {
     static ref FD: OwnedFormatItem =
@@ -28,3 +31,119 @@ pub fn date_option_as_human_friendly<S: Serializer>(
         None => s.serialize_str(""),
     }
 }
+
+pub fn primitive_date_as_human_friendly<S: Serializer>(
+    date: &PrimitiveDateTime,
+    s: S,
+) -> Result<S::Ok, S::Error> {
+    date_as_human_friendly(&date.assume_utc(), s)
+}
+
+pub fn timezone_option_as_name<S: Serializer>(tz: &Option<Tz>, s: S) -> Result<S::Ok, S::Error> {
+    match tz {
+        Some(tz) => s.serialize_str(tz.name()),
+        None => s.serialize_none(),
+    }
+}
+
+/// The timezone anonymous visitors see WorldAnvil dates in.
+/// This matches the timezone the WorldAnvil API reports its own dates in.
+pub fn default_timezone() -> Tz {
+    envvar("WORLDANVIL_TIMEZONE")
+        .ok()
+        .and_then(|tz| tz.parse().ok())
+        .unwrap_or(Tz::Europe__London)
+}
+
+/// Pick the timezone a page was rendered in.
+/// A timezone found on the page wins over the one the user configured, then the default.
+pub fn resolve_timezone(page_timezone: Option<Tz>, user_timezone: Option<&str>) -> Tz {
+    page_timezone
+        .or_else(|| user_timezone.and_then(|tz| tz.parse().ok()))
+        .unwrap_or_else(default_timezone)
+}
+
+/// Interpret a wall clock time in the given timezone.
+/// Ambiguous times pick the earlier instant. Times skipped by DST use the offset in effect at
+/// the same wall clock time in UTC.
+pub fn assume_timezone(local: PrimitiveDateTime, tz: Tz) -> OffsetDateTime {
+    let naive = NaiveDate::from_ymd_opt(local.year(), local.month() as u32, local.day() as u32)
+        .and_then(|date| {
+            date.and_hms_opt(
+                local.hour() as u32,
+                local.minute() as u32,
+                local.second() as u32,
+            )
+        })
+        .expect("time dates are always valid chrono dates");
+    let offset = tz
+        .offset_from_local_datetime(&naive)
+        .earliest()
+        .unwrap_or_else(|| tz.offset_from_utc_datetime(&naive))
+        .fix()
+        .local_minus_utc();
+    let offset = UtcOffset::from_whole_seconds(offset).unwrap_or(UtcOffset::UTC);
+    local.assume_offset(offset)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use time::macros::datetime;
+
+    #[test]
+    fn test_assume_timezone() {
+        let cases = [
+            (
+                Tz::UTC,
+                datetime!(2024-08-08 13:43),
+                datetime!(2024-08-08 13:43 UTC),
+            ),
+            (
+                Tz::Europe__London,
+                datetime!(2024-08-08 13:43),
+                datetime!(2024-08-08 12:43 UTC),
+            ),
+            (
+                Tz::Europe__London,
+                datetime!(2024-01-08 13:43),
+                datetime!(2024-01-08 13:43 UTC),
+            ),
+            (
+                Tz::America__New_York,
+                datetime!(2024-08-08 13:43),
+                datetime!(2024-08-08 17:43 UTC),
+            ),
+            (
+                Tz::Asia__Kolkata,
+                datetime!(2024-08-08 13:43),
+                datetime!(2024-08-08 08:13 UTC),
+            ),
+            // Falls in the gap when clocks go forward
+            (
+                Tz::Europe__London,
+                datetime!(2024-03-31 01:30),
+                datetime!(2024-03-31 00:30 UTC),
+            ),
+        ];
+        for (tz, local, expected) in cases {
+            assert_eq!(assume_timezone(local, tz), expected, "{local} in {tz}");
+        }
+    }
+
+    #[test]
+    fn test_resolve_timezone() {
+        assert_eq!(
+            resolve_timezone(Some(Tz::Asia__Tokyo), Some("Europe/Berlin")),
+            Tz::Asia__Tokyo
+        );
+        assert_eq!(
+            resolve_timezone(None, Some("Europe/Berlin")),
+            Tz::Europe__Berlin
+        );
+        assert_eq!(
+            resolve_timezone(None, Some("Not/A_Zone")),
+            default_timezone()
+        );
+    }
+}
@@ -1,6 +1,10 @@
 use crate::db::pgacquire::PgAcquire;
-use crate::db::schema::{Article, ArticleAndStatus, ArticleDetails, RawArticleAndStatus};
+use crate::db::schema::{
+    Article, ArticleAndStatus, ArticleDetails, ArticleMetadata, PageInsert, RawArticleAndStatus,
+};
+use crate::parser::content::normalize_mention;
 use sqlx::PgConnection;
+use time::OffsetDateTime;
 
 pub async fn register_article<'a, A: PgAcquire<'a>>(
     user_id: i64,
@@ -24,59 +28,95 @@ pub async fn register_article<'a, A: PgAcquire<'a>>(
     .map(|r| r.id)
 }
 
-pub async fn register_articles<'a, A: PgAcquire<'a>>(
+/// Register every commentable page of a world.
+/// If `pages` is every page of the world, the ones that are gone are forgotten.
+pub async fn register_pages<'a, A: PgAcquire<'a>>(
     user_id: i64,
     world_id: i64,
-    urls: &[String],
-    titles: &[String],
-    worldanvil_ids: &[String],
+    pages: Vec<PageInsert>,
+    complete: bool,
     conn: A,
-) -> Result<Option<i64>, sqlx::Error> {
+) -> Result<Vec<i64>, sqlx::Error> {
     let mut conn = conn.acquire().await?;
-    // Delete articles that no longer exist
-    sqlx::query!(
-        "
-        DELETE FROM article
-        WHERE worldanvil_id <> ANY($1::text[]);
-        ",
-        worldanvil_ids,
-    )
-    .execute(&mut *conn)
-    .await?;
-    sqlx::query!(
+    let mut kinds = Vec::new();
+    let mut urls = Vec::new();
+    let mut titles = Vec::new();
+    let mut worldanvil_ids = Vec::new();
+    let mut updated_ats = Vec::new();
+    pages.into_iter().for_each(|page| {
+        kinds.push(page.kind.to_string());
+        urls.push(page.url);
+        titles.push(page.title);
+        worldanvil_ids.push(page.worldanvil_id);
+        updated_ats.push(page.updated_at);
+    });
+    // Delete pages that no longer exist
+    if complete {
+        sqlx::query!(
+            "
+            DELETE FROM article
+            WHERE user_id=$1 AND world_id=$2 AND NOT worldanvil_id = ANY($3::text[]);
+            ",
+            user_id,
+            world_id,
+            &worldanvil_ids,
+        )
+        .execute(&mut *conn)
+        .await?;
+    }
+    // Pages archived because they were gone are only tried again once they changed.
+    let returning = sqlx::query!(
         "
-        INSERT INTO article(user_id, world_id, url, title, worldanvil_id)
+        INSERT INTO article(user_id, world_id, kind, url, title, worldanvil_id, updated_at)
         SELECT $1 as user_id, $2 as world_id, *
-        FROM UNNEST($3::text[], $4::text[], $5::text[])
+        FROM UNNEST($3::text[], $4::text[], $5::text[], $6::text[], $7::timestamptz[])
         ON CONFLICT (worldanvil_id) DO UPDATE
-        SET url = excluded.url, title = excluded.title
+        SET kind = excluded.kind, url = excluded.url, title = excluded.title,
+            updated_at = excluded.updated_at,
+            archived = article.archived AND article.url = excluded.url
+                AND article.updated_at IS NOT DISTINCT FROM excluded.updated_at
         RETURNING id;",
         user_id,
         world_id,
-        urls,
-        titles,
-        worldanvil_ids,
+        &kinds,
+        &urls,
+        &titles,
+        &worldanvil_ids,
+        &updated_ats as &[Option<OffsetDateTime>],
     )
-    .fetch_optional(&mut *conn)
-    .await
-    .map(|r| r.map(|r| r.id))
+    .fetch_all(&mut *conn)
+    .await?;
+    Ok(returning.into_iter().map(|record| record.id).collect())
 }
 
 /// Create or update the article content entry
 pub async fn update_article_content<'a, A: PgAcquire<'a>>(
     conn: A,
     article_id: i64,
-    worldanvil_id: &str,
+    worldanvil_id: Option<&str>,
     title: &str,
+    metadata: &ArticleMetadata,
 ) -> sqlx::Result<()> {
     let mut conn = conn.acquire().await?;
     sqlx::query!(
-        "INSERT INTO article_content(article_id, worldanvil_id, title) VALUES ($1, $2, $3)
+        "INSERT INTO article_content(
+            article_id, worldanvil_id, title,
+            author, word_count, likes, views, tags, article_type, comments_disabled
+        )
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10)
         ON CONFLICT(article_id)
-        DO UPDATE SET worldanvil_id=$2, title=$3;",
+        DO UPDATE SET worldanvil_id=$2, title=$3, author=$4, word_count=$5, likes=$6, views=$7,
+            tags=$8, article_type=$9, comments_disabled=$10;",
         article_id,
         worldanvil_id,
         title,
+        metadata.author,
+        metadata.word_count,
+        metadata.likes,
+        metadata.views,
+        &metadata.tags,
+        metadata.article_type,
+        metadata.comments_disabled,
     )
     .execute(&mut *conn)
     .await?;
@@ -126,7 +166,12 @@ pub async fn get_unqueued_article_ids<'a, A: PgAcquire<'a>>(
         LEFT JOIN article_queue
         ON article_queue.id=max_aq.id
         WHERE (article_queue.done is NULL or article_queue.done=true)
-            AND article.user_id=$1 AND article.world_id=$2
+            AND article.user_id=$1 AND article.world_id=$2 AND NOT article.archived
+            -- There is no point checking articles nobody can comment on
+            AND NOT EXISTS (
+                SELECT 1 FROM article_content
+                WHERE article_content.article_id=article.id AND comments_disabled
+            )
     ",
     )
     .bind(user_id)
@@ -144,7 +189,9 @@ pub async fn get_article<'a, A: PgAcquire<'a>>(
     let mut conn = conn.acquire().await?;
     sqlx::query_as!(
         Article,
-        "SELECT id, user_id, world_id, url, last_checked FROM article WHERE id=$1 AND user_id=$2;",
+        "SELECT id, user_id, world_id, kind, worldanvil_id, url, last_checked,
+            etag, last_modified, comments_hash
+        FROM article WHERE id=$1 AND user_id=$2;",
         article_id,
         user_id,
     )
@@ -159,7 +206,9 @@ pub async fn get_article_conn(
 ) -> sqlx::Result<Article> {
     sqlx::query_as!(
         Article,
-        "SELECT id, user_id, world_id, url, last_checked FROM article WHERE id=$1 AND user_id=$2;",
+        "SELECT id, user_id, world_id, kind, worldanvil_id, url, last_checked,
+            etag, last_modified, comments_hash
+        FROM article WHERE id=$1 AND user_id=$2;",
         article_id,
         user_id,
     )
@@ -186,18 +235,28 @@ pub async fn get_article_details<'a, A: PgAcquire<'a>>(
     .await
 }
 
+/// List the articles of a world with their check status, those needing attention first.
+/// Threads that mention `user_name` count as priority comments.
 pub async fn get_articles_and_status<'a, A: PgAcquire<'a>>(
     user_id: &i64,
     world_id: &i64,
+    user_name: Option<&str>,
     conn: A,
 ) -> sqlx::Result<Vec<ArticleAndStatus>> {
     let mut conn = conn.acquire().await?;
     let res = sqlx::query_as!(
         RawArticleAndStatus,
         r#"SELECT
-            article.id AS article_id, title, url, last_checked,
-            done as "done?", error as "error?", error_msg as "error_msg?", comments.count as unanswered_comments
+            article.id AS article_id, kind, article.title, url, last_checked, archived,
+            done as "done?", error as "error?", error_msg as "error_msg?", warning_msg as "warning_msg?",
+            attempts as "attempts?", last_error as "last_error?", dead as "dead?",
+            cancelled as "cancelled?",
+            comments.count as unanswered_comments, priority.count as priority_comments,
+            author as "author?", word_count as "word_count?", likes as "likes?",
+            views as "views?", tags as "tags?", article_type as "article_type?",
+            comments_disabled as "comments_disabled?"
         FROM article
+        LEFT JOIN article_content ON article_content.article_id = article.id
         LEFT JOIN (
             SELECT MAX(id) AS id, article_id
             FROM article_queue
@@ -205,20 +264,35 @@ pub async fn get_articles_and_status<'a, A: PgAcquire<'a>>(
         ) AS max_aq
         ON article.id = max_aq.article_id
         LEFT JOIN (
-            SELECT id, done, error, error_msg
+            SELECT id, done, error, error_msg, warning_msg, attempts, last_error, dead, cancelled
             FROM article_queue
         ) AS aq
         ON max_aq.id = aq.id
         LEFT JOIN (
             SELECT COUNT(*) as count, article_id
             FROM comment
+            WHERE NOT answered AND NOT removed
             GROUP BY article_id
         ) as comments
         ON comments.article_id = article.id
+        LEFT JOIN (
+            SELECT COUNT(*) as count, article_id
+            FROM comment
+            WHERE NOT answered AND NOT removed AND (
+                question OR $3 = ANY(mentions) OR EXISTS (
+                    SELECT 1 FROM comment_replies AS reply
+                    WHERE reply.parent = comment.id AND NOT reply.removed
+                        AND (reply.question OR $3 = ANY(reply.mentions))
+                )
+            )
+            GROUP BY article_id
+        ) as priority
+        ON priority.article_id = article.id
         WHERE article.user_id=$1 AND article.world_id=$2
-        ORDER BY unanswered_comments DESC NULLS LAST"#,
+        ORDER BY priority_comments DESC NULLS LAST, unanswered_comments DESC NULLS LAST"#,
         user_id,
         world_id,
+        user_name.map(normalize_mention),
     )
     .fetch_all(&mut *conn)
     .await?
@@ -228,6 +302,67 @@ pub async fn get_articles_and_status<'a, A: PgAcquire<'a>>(
     Ok(res)
 }
 
+/// Stop checking an article that no longer exists on WorldAnvil.
+pub async fn archive_article<'a, A: PgAcquire<'a>>(
+    conn: A,
+    article_id: i64,
+    user_id: i64,
+) -> sqlx::Result<()> {
+    let mut conn = conn.acquire().await?;
+    sqlx::query!(
+        "UPDATE article SET archived=TRUE WHERE id=$1 AND user_id=$2",
+        article_id,
+        user_id,
+    )
+    .execute(&mut *conn)
+    .await?;
+    Ok(())
+}
+
+/// Point an article at its new url, e.g. after its slug changed.
+pub async fn set_article_url<'a, A: PgAcquire<'a>>(
+    conn: A,
+    article_id: i64,
+    user_id: i64,
+    url: &str,
+) -> sqlx::Result<()> {
+    let mut conn = conn.acquire().await?;
+    sqlx::query!(
+        "UPDATE article SET url=$3 WHERE id=$1 AND user_id=$2",
+        article_id,
+        user_id,
+        url,
+    )
+    .execute(&mut *conn)
+    .await?;
+    Ok(())
+}
+
+/// Remember the validators the article page was served with and the hash of its comments,
+/// so the next check can tell whether anything changed.
+pub async fn set_article_page_cache<'a, A: PgAcquire<'a>>(
+    conn: A,
+    article_id: i64,
+    user_id: i64,
+    etag: Option<&str>,
+    last_modified: Option<&str>,
+    comments_hash: Option<&str>,
+) -> sqlx::Result<()> {
+    let mut conn = conn.acquire().await?;
+    sqlx::query!(
+        "UPDATE article SET etag=$3, last_modified=$4, comments_hash=$5
+        WHERE id=$1 AND user_id=$2",
+        article_id,
+        user_id,
+        etag,
+        last_modified,
+        comments_hash,
+    )
+    .execute(&mut *conn)
+    .await?;
+    Ok(())
+}
+
 pub async fn set_article_checked_time<'a, A: PgAcquire<'a>>(
     user_id: &i64,
     article_id: &i64,
@@ -246,3 +381,43 @@ pub async fn set_article_checked_time<'a, A: PgAcquire<'a>>(
     .await?;
     Ok(())
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::db::schema::PageKind;
+    use crate::db::test_queries::add_test_article;
+    use sqlx::PgPool;
+    use time::macros::datetime;
+
+    #[sqlx::test]
+    async fn test_register_pages_archived(pool: PgPool) -> sqlx::Result<()> {
+        let mut conn = pool.acquire().await?;
+        let url = "https://www.worldanvil.com/w/solaris-nnie/a/chewpaper-material";
+        let (user, world, _) = add_test_article(&mut *conn, "reader", url).await?;
+        let page = |url: &str, updated_at| PageInsert {
+            kind: PageKind::Article,
+            worldanvil_id: "4cdfec2c-b875-4dc6-b5c9-146470e9ac80".to_string(),
+            title: "Chewpaper".to_string(),
+            url: url.to_string(),
+            updated_at,
+        };
+        let edited = Some(datetime!(2024-08-08 13:43 UTC));
+        let ids = register_pages(user, world, vec![page(url, edited)], true, &mut *conn).await?;
+        archive_article(&mut *conn, ids[0], user).await?;
+        let archived =
+            |id: i64| sqlx::query_scalar!("SELECT archived FROM article WHERE id=$1", id);
+        // Still listed as it was, the page stays archived.
+        register_pages(user, world, vec![page(url, edited)], true, &mut *conn).await?;
+        assert!(archived(ids[0]).fetch_one(&mut *conn).await?);
+        // Once it was edited or moved, it is checked again.
+        let later = Some(datetime!(2024-08-09 18:02 UTC));
+        register_pages(user, world, vec![page(url, later)], true, &mut *conn).await?;
+        assert!(!archived(ids[0]).fetch_one(&mut *conn).await?);
+        archive_article(&mut *conn, ids[0], user).await?;
+        let moved = "https://www.worldanvil.com/w/solaris-nnie/a/chewing-paper-material";
+        register_pages(user, world, vec![page(moved, later)], true, &mut *conn).await?;
+        assert!(!archived(ids[0]).fetch_one(&mut *conn).await?);
+        Ok(())
+    }
+}
@@ -1,5 +1,6 @@
 use crate::db::pgacquire::PgAcquire;
-use crate::db::schema::{Comment, CommentInsert};
+use crate::db::schema::{Comment, CommentInsert, CommentReply, CommentReplyInsert};
+use std::collections::HashMap;
 
 /// Fetch comments on a specified article
 pub async fn get_comments<'a, A: PgAcquire<'a>>(
@@ -10,9 +11,13 @@ pub async fn get_comments<'a, A: PgAcquire<'a>>(
     let mut conn = conn.acquire().await?;
     sqlx::query_as!(
         Comment,
-        "SELECT id, user_id, author_id, article_id, content, date, starred, deleted
+        r#"SELECT comment.id, user_id, author_id, wa_user.name as "author_name?", article_id,
+            identity, content, content_html, date, starred, deleted, answered, removed,
+            mentions, question
         FROM comment
-        WHERE article_id=$1 AND user_id=$2;",
+        LEFT JOIN wa_user ON wa_user.id = comment.author_id
+        WHERE article_id=$1 AND user_id=$2
+        ORDER BY date ASC;"#,
         article_id,
         user_id,
     )
@@ -20,49 +25,406 @@ pub async fn get_comments<'a, A: PgAcquire<'a>>(
     .await
 }
 
-/// Insert unanswered comments
-pub async fn insert_comments<'a, A: PgAcquire<'a>>(
+/// Fetch the replies to all comments on a specified article
+pub async fn get_replies<'a, A: PgAcquire<'a>>(
     conn: A,
     article_id: i64,
     user_id: i64,
+) -> sqlx::Result<Vec<CommentReply>> {
+    let mut conn = conn.acquire().await?;
+    sqlx::query_as!(
+        CommentReply,
+        r#"SELECT comment_replies.id, user_id as "user_id!", author_id,
+            wa_user.name as "author_name?", article_id as "article_id!", parent as "parent!",
+            identity, content, content_html, date, starred, deleted, removed, mentions, question
+        FROM comment_replies
+        LEFT JOIN wa_user ON wa_user.id = comment_replies.author_id
+        WHERE article_id=$1 AND user_id=$2 AND parent IS NOT NULL
+        ORDER BY date ASC;"#,
+        article_id,
+        user_id,
+    )
+    .fetch_all(&mut *conn)
+    .await
+}
+
+/// Insert root comments or update the existing ones with the same identity, returning their ids
+/// in the order the comments were given. User-set flags such as `starred` and `deleted` are left
+/// untouched, and so are stored dates when the page only gave a relative one.
+pub async fn upsert_comments<'a, A: PgAcquire<'a>>(
+    conn: A,
     comments: Vec<CommentInsert>,
-) -> sqlx::Result<()> {
+) -> sqlx::Result<Vec<i64>> {
+    let mut user_ids = vec![];
+    let mut article_ids = vec![];
     let mut author_ids = vec![];
+    let mut identities = vec![];
     let mut contents = vec![];
+    let mut contents_html = vec![];
     let mut dates = vec![];
+    let mut approximate_dates = vec![];
+    let mut answered = vec![];
+    // Postgres arrays cannot be jagged, so the lists are passed space separated.
+    // Neither names nor urls contain spaces.
+    let mut mentions = vec![];
+    let mut links = vec![];
+    let mut questions = vec![];
     comments.into_iter().for_each(|comment| {
+        user_ids.push(comment.user_id);
+        article_ids.push(comment.article_id);
         author_ids.push(comment.author_id);
+        identities.push(comment.identity);
         contents.push(comment.content);
+        contents_html.push(comment.content_html);
         dates.push(comment.date);
+        approximate_dates.push(comment.approximate_date);
+        answered.push(comment.answered);
+        mentions.push(comment.mentions.join(" "));
+        links.push(comment.links.join(" "));
+        questions.push(comment.question);
+    });
+    let mut conn = conn.acquire().await?;
+    let rows = sqlx::query!(
+        "INSERT INTO comment(
+            user_id, article_id, author_id, identity, content, content_html, date,
+            date_approximate, answered, mentions, links, question
+        )
+        SELECT user_id, article_id, author_id, identity, content, content_html, date,
+            date_approximate, answered, string_to_array(mentions, ' '),
+            string_to_array(links, ' '), question
+        FROM UNNEST(
+            $1::bigint[], $2::bigint[], $3::bigint[], $4::text[], $5::text[], $6::text[],
+            $7::timestamp with time zone[], $8::bool[], $9::bool[], $10::text[], $11::text[],
+            $12::bool[]
+        ) AS t(
+            user_id, article_id, author_id, identity, content, content_html, date,
+            date_approximate, answered, mentions, links, question
+        )
+        ON CONFLICT (article_id, identity) DO UPDATE SET
+            author_id=EXCLUDED.author_id,
+            content=EXCLUDED.content,
+            content_html=EXCLUDED.content_html,
+            mentions=EXCLUDED.mentions,
+            links=EXCLUDED.links,
+            question=EXCLUDED.question,
+            date=CASE WHEN EXCLUDED.date_approximate THEN comment.date ELSE EXCLUDED.date END,
+            date_approximate=comment.date_approximate AND EXCLUDED.date_approximate,
+            answered=EXCLUDED.answered,
+            removed=FALSE
+        RETURNING id, identity;",
+        &user_ids,
+        &article_ids,
+        &author_ids as _,
+        &identities,
+        &contents,
+        &contents_html,
+        &dates,
+        &approximate_dates,
+        &answered,
+        &mentions,
+        &links,
+        &questions,
+    )
+    .fetch_all(&mut *conn)
+    .await?;
+    // RETURNING does not promise any order, so match the ids back up by identity.
+    let ids: HashMap<_, _> = rows.into_iter().map(|r| (r.identity, r.id)).collect();
+    Ok(identities.iter().map(|identity| ids[identity]).collect())
+}
+
+/// Insert replies to root comments, updating any that already exist.
+/// Stored dates are kept when the page only gave a relative one.
+/// Each reply is paired with the id of its root comment.
+pub async fn upsert_replies<'a, A: PgAcquire<'a>>(
+    conn: A,
+    replies: Vec<(i64, CommentReplyInsert)>,
+) -> sqlx::Result<()> {
+    let mut parents = vec![];
+    let mut user_ids = vec![];
+    let mut article_ids = vec![];
+    let mut author_ids = vec![];
+    let mut identities = vec![];
+    let mut contents = vec![];
+    let mut contents_html = vec![];
+    let mut dates = vec![];
+    let mut approximate_dates = vec![];
+    let mut mentions = vec![];
+    let mut links = vec![];
+    let mut questions = vec![];
+    replies.into_iter().for_each(|(parent, reply)| {
+        parents.push(parent);
+        user_ids.push(reply.user_id);
+        article_ids.push(reply.article_id);
+        author_ids.push(reply.author_id);
+        identities.push(reply.identity);
+        contents.push(reply.content);
+        contents_html.push(reply.content_html);
+        dates.push(reply.date);
+        approximate_dates.push(reply.approximate_date);
+        mentions.push(reply.mentions.join(" "));
+        links.push(reply.links.join(" "));
+        questions.push(reply.question);
     });
     let mut conn = conn.acquire().await?;
     sqlx::query!(
-        "INSERT INTO comment(user_id, article_id, author_id, content, date)
-        SELECT $1, $2, * FROM UNNEST($3::bigint[], $4::text[], $5::timestamp with time zone[])",
-        user_id,
-        article_id,
-        &author_ids,
+        "INSERT INTO comment_replies(
+            parent, user_id, article_id, author_id, identity, content, content_html, date,
+            date_approximate, mentions, links, question
+        )
+        SELECT parent, user_id, article_id, author_id, identity, content, content_html, date,
+            date_approximate, string_to_array(mentions, ' '), string_to_array(links, ' '),
+            question
+        FROM UNNEST(
+            $1::bigint[], $2::bigint[], $3::bigint[], $4::bigint[], $5::text[], $6::text[],
+            $7::text[], $8::timestamp with time zone[], $9::bool[], $10::text[], $11::text[],
+            $12::bool[]
+        ) AS t(
+            parent, user_id, article_id, author_id, identity, content, content_html, date,
+            date_approximate, mentions, links, question
+        )
+        ON CONFLICT (article_id, identity) DO UPDATE SET
+            parent=EXCLUDED.parent,
+            author_id=EXCLUDED.author_id,
+            content=EXCLUDED.content,
+            content_html=EXCLUDED.content_html,
+            mentions=EXCLUDED.mentions,
+            links=EXCLUDED.links,
+            question=EXCLUDED.question,
+            date=CASE WHEN EXCLUDED.date_approximate
+                THEN comment_replies.date ELSE EXCLUDED.date END,
+            date_approximate=comment_replies.date_approximate AND EXCLUDED.date_approximate,
+            removed=FALSE",
+        &parents,
+        &user_ids,
+        &article_ids,
+        &author_ids as _,
+        &identities,
         &contents,
+        &contents_html,
         &dates,
+        &approximate_dates,
+        &mentions,
+        &links,
+        &questions,
     )
     .execute(&mut *conn)
     .await?;
     Ok(())
 }
 
-/// Delete all db comments for an article
-pub async fn delete_comments<'a, A: PgAcquire<'a>>(
+/// Give comments first stored with a relative date the identity they have now that the page shows
+/// their full date. `renames` pairs the identity they were stored with and the new one.
+/// Nothing is renamed onto an identity that is already taken.
+pub async fn rename_comment_identities<'a, A: PgAcquire<'a>>(
+    conn: A,
+    article_id: i64,
+    renames: &[(String, String)],
+) -> sqlx::Result<()> {
+    let (old, new): (Vec<_>, Vec<_>) = renames.iter().cloned().unzip();
+    let mut conn = conn.acquire().await?;
+    sqlx::query!(
+        "UPDATE comment SET identity = t.new
+        FROM UNNEST($2::text[], $3::text[]) AS t(old, new)
+        WHERE article_id = $1 AND identity = t.old
+            AND NOT EXISTS (SELECT 1 FROM comment WHERE article_id = $1 AND identity = t.new);",
+        article_id,
+        &old,
+        &new,
+    )
+    .execute(&mut *conn)
+    .await?;
+    sqlx::query!(
+        "UPDATE comment_replies SET identity = t.new
+        FROM UNNEST($2::text[], $3::text[]) AS t(old, new)
+        WHERE article_id = $1 AND identity = t.old
+            AND NOT EXISTS (
+                SELECT 1 FROM comment_replies WHERE article_id = $1 AND identity = t.new
+            );",
+        article_id,
+        &old,
+        &new,
+    )
+    .execute(&mut *conn)
+    .await?;
+    Ok(())
+}
+
+/// Mark comments and replies that are no longer on the page as removed.
+/// `comments` and `replies` are the identities that are still present.
+pub async fn mark_removed_comments<'a, A: PgAcquire<'a>>(
     conn: A,
     article_id: i64,
     user_id: i64,
+    comments: &[String],
+    replies: &[String],
 ) -> sqlx::Result<()> {
     let mut conn = conn.acquire().await?;
     sqlx::query!(
-        "DELETE FROM comment WHERE article_id=$1 AND user_id=$2;",
+        "UPDATE comment_replies SET removed=TRUE
+        WHERE article_id=$1 AND user_id=$2 AND NOT removed AND NOT (identity = ANY($3));",
         article_id,
-        user_id
+        user_id,
+        replies,
+    )
+    .execute(&mut *conn)
+    .await?;
+    sqlx::query!(
+        "UPDATE comment SET removed=TRUE
+        WHERE article_id=$1 AND user_id=$2 AND NOT removed AND NOT (identity = ANY($3));",
+        article_id,
+        user_id,
+        comments,
     )
     .execute(&mut *conn)
     .await?;
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::schema::CommentThread;
+    use crate::db::test_queries::add_test_article;
+    use sqlx::PgPool;
+    use time::macros::datetime;
+
+    fn comment(user_id: i64, article_id: i64, identity: &str, content: &str) -> CommentInsert {
+        CommentInsert {
+            user_id,
+            author_id: None,
+            article_id,
+            identity: identity.to_string(),
+            content: content.to_string(),
+            content_html: format!("<p>{content}</p>"),
+            mentions: vec![],
+            links: vec![],
+            question: false,
+            date: datetime!(2024-08-08 13:43 UTC),
+            approximate_date: false,
+            answered: false,
+        }
+    }
+
+    /// Re-syncing a comment keeps its id and user-set flags, and vanished comments are marked removed.
+    #[sqlx::test]
+    async fn test_sync_comments(pool: PgPool) -> anyhow::Result<()> {
+        let mut conn = pool.acquire().await?;
+        let (user, _, article_id) = add_test_article(&mut *conn, "user1", "myurl").await?;
+
+        let ids = upsert_comments(
+            &mut *conn,
+            vec![
+                comment(user, article_id, "a", "first"),
+                comment(user, article_id, "b", "second"),
+            ],
+        )
+        .await?;
+        let kept = ids[0];
+        sqlx::query!("UPDATE comment SET starred=TRUE WHERE id=$1", kept)
+            .execute(&mut *conn)
+            .await?;
+
+        // The next check sees an edited "a" and no longer sees "b".
+        let resynced =
+            upsert_comments(&mut *conn, vec![comment(user, article_id, "a", "edited")]).await?;
+        assert_eq!(resynced, [kept]);
+        mark_removed_comments(&mut *conn, article_id, user, &["a".to_string()], &[]).await?;
+
+        let comments = get_comments(&mut *conn, article_id, user).await?;
+        assert_eq!(comments.len(), 2);
+        let a = comments.iter().find(|c| c.key() == "a").unwrap();
+        assert!(a.starred);
+        assert!(!a.removed);
+        assert_eq!(a.content, "edited");
+        let b = comments.iter().find(|c| c.key() == "b").unwrap();
+        assert!(b.removed);
+        Ok(())
+    }
+
+    /// A relative date does not overwrite the stored one, and a comment first stored with a
+    /// relative date takes its precise identity once the page shows the full date.
+    #[sqlx::test]
+    async fn test_sync_relative_dates(pool: PgPool) -> anyhow::Result<()> {
+        let mut conn = pool.acquire().await?;
+        let (user, _, article_id) = add_test_article(&mut *conn, "user1", "myurl").await?;
+        let relative = |date| CommentInsert {
+            date,
+            approximate_date: true,
+            ..comment(user, article_id, "ghost@~185f8db3", "Hello")
+        };
+
+        let id =
+            upsert_comments(&mut *conn, vec![relative(datetime!(2024-08-08 13:43 UTC))]).await?[0];
+        // A later check reads "2 hours ago" as a different time.
+        upsert_comments(&mut *conn, vec![relative(datetime!(2024-08-09 18:02 UTC))]).await?;
+        let comments = get_comments(&mut *conn, article_id, user).await?;
+        assert_eq!(comments[0].date, datetime!(2024-08-08 13:43 UTC));
+
+        rename_comment_identities(
+            &mut *conn,
+            article_id,
+            &[(
+                "ghost@~185f8db3".to_string(),
+                "ghost@2024-08-08T13:40".to_string(),
+            )],
+        )
+        .await?;
+        let precise = CommentInsert {
+            date: datetime!(2024-08-08 13:40 UTC),
+            ..comment(user, article_id, "ghost@2024-08-08T13:40", "Hello")
+        };
+        assert_eq!(upsert_comments(&mut *conn, vec![precise]).await?, [id]);
+        let comments = get_comments(&mut *conn, article_id, user).await?;
+        assert_eq!(comments.len(), 1);
+        assert_eq!(comments[0].date, datetime!(2024-08-08 13:40 UTC));
+        Ok(())
+    }
+
+    /// Mentions and questions survive the round trip and flag the thread.
+    #[sqlx::test]
+    async fn test_priority_threads(pool: PgPool) -> anyhow::Result<()> {
+        let mut conn = pool.acquire().await?;
+        let (user, _, article_id) = add_test_article(&mut *conn, "user1", "myurl").await?;
+        let parent =
+            upsert_comments(&mut *conn, vec![comment(user, article_id, "a", "Nice")]).await?[0];
+        let reply = |identity: &str, mentions: &[&str], links: &[&str]| {
+            let reply = CommentReplyInsert {
+                user_id: user,
+                author_id: None,
+                article_id,
+                identity: identity.to_string(),
+                content: String::new(),
+                content_html: String::new(),
+                mentions: mentions.iter().map(|m| m.to_string()).collect(),
+                links: links.iter().map(|l| l.to_string()).collect(),
+                question: false,
+                date: datetime!(2024-08-09 10:00 UTC),
+                approximate_date: false,
+            };
+            (parent, reply)
+        };
+        let replies = vec![
+            reply("b", &[], &[]),
+            reply(
+                "c",
+                &["nnie", "tyrdal"],
+                &["https://www.worldanvil.com/w/solaris-nnie"],
+            ),
+        ];
+        upsert_replies(&mut *conn, replies).await?;
+
+        let replies = get_replies(&mut *conn, article_id, user).await?;
+        assert!(replies[0].mentions.is_empty());
+        assert_eq!(replies[1].mentions, ["nnie", "tyrdal"]);
+        let comments = get_comments(&mut *conn, article_id, user).await?;
+        let thread =
+            CommentThread::new(comments.into_iter().next().unwrap(), replies, Some("Nnie"));
+        assert!(thread.priority);
+        let comments = get_comments(&mut *conn, article_id, user).await?;
+        let replies = get_replies(&mut *conn, article_id, user).await?;
+        let thread =
+            CommentThread::new(comments.into_iter().next().unwrap(), replies, Some("user1"));
+        assert!(!thread.priority);
+        Ok(())
+    }
+}
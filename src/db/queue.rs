@@ -1,12 +1,107 @@
-use crate::db::pgacquire::PgAcquire;
-use crate::db::schema::{ArticleQueueEntry, UserQueue};
-use influxdb::InfluxDbWriteable;
+use crate::db::schema::{
+    ArticleQueueEntry, PendingTasks, RecheckCandidate, TaskPriority, UserQueue, UserQueueSettings,
+};
+use dotenv::var as envvar;
 use sqlx::{FromRow, PgConnection, Postgres};
-use time::OffsetDateTime;
+use std::collections::HashMap;
+
+/// The channel workers listen on to hear that there may be work for them.
+pub const QUEUE_CHANNEL: &str = "article_queue";
+
+/// Wake up the workers listening on QUEUE_CHANNEL once the current transaction commits.
+pub async fn notify_queue(conn: &mut PgConnection) -> sqlx::Result<()> {
+    sqlx::query!("SELECT pg_notify($1, '');", QUEUE_CHANNEL)
+        .execute(conn)
+        .await?;
+    Ok(())
+}
+
+/// How the workers share their time between users, for users without settings of their own.
+#[derive(Clone, Copy, Debug)]
+pub struct QueueDefaults {
+    /// The least time between two checks for a user, before their weight is applied.
+    pub min_interval: std::time::Duration,
+    /// How many tasks a user can have queued in bulk or by the scheduler, if limited.
+    pub max_pending: Option<i64>,
+    /// How many checks are made for a user per day, if limited.
+    pub daily_budget: Option<i32>,
+}
+
+impl Default for QueueDefaults {
+    fn default() -> Self {
+        Self {
+            min_interval: std::time::Duration::from_secs(2),
+            max_pending: None,
+            daily_budget: None,
+        }
+    }
+}
+
+impl QueueDefaults {
+    /// Read from QUEUE_MIN_INTERVAL_SECS, QUEUE_MAX_PENDING and QUEUE_DAILY_BUDGET.
+    /// The limits are off unless set.
+    pub fn from_env() -> Self {
+        let defaults = Self::default();
+        Self {
+            min_interval: envvar("QUEUE_MIN_INTERVAL_SECS")
+                .ok()
+                .and_then(|secs| secs.parse().ok())
+                .and_then(|secs| std::time::Duration::try_from_secs_f64(secs).ok())
+                .unwrap_or(defaults.min_interval),
+            max_pending: envvar("QUEUE_MAX_PENDING")
+                .ok()
+                .and_then(|max| max.parse().ok()),
+            daily_budget: envvar("QUEUE_DAILY_BUDGET")
+                .ok()
+                .and_then(|budget| budget.parse().ok()),
+        }
+    }
+}
+
+/// How long until some user with waiting tasks can be worked on, going by the same rules as
+/// get_next_user. None if nobody has any tasks, or somebody can be worked on already.
+/// Users who used up their daily budget wait until midnight.
+pub async fn time_until_next_task(
+    defaults: &QueueDefaults,
+    conn: &mut PgConnection,
+) -> sqlx::Result<Option<std::time::Duration>> {
+    let res = sqlx::query!(
+        r#"
+        SELECT EXTRACT(EPOCH FROM MIN(GREATEST(
+            user_queue.last_updated
+                + make_interval(secs => COALESCE(user_queue.min_interval_secs, $1) / weight),
+            aq.next_attempt_at,
+            CASE WHEN budget_day = CURRENT_DATE
+                AND checks_today >= COALESCE(daily_budget, $2::int, 2147483647)
+            THEN (CURRENT_DATE + 1)::timestamptz END
+        )) - NOW())::float8 as wait
+        FROM user_queue
+        JOIN (
+            SELECT user_id, MIN(next_attempt_at) as next_attempt_at
+            FROM article_queue
+            WHERE done <> true
+            GROUP BY user_id
+        ) as aq
+        ON user_queue.user_id = aq.user_id;
+        "#,
+        defaults.min_interval.as_secs_f64(),
+        defaults.daily_budget,
+    )
+    .fetch_one(conn)
+    .await?;
+    Ok(res
+        .wait
+        .filter(|secs| *secs > 0.0)
+        .map(std::time::Duration::from_secs_f64))
+}
 
 /// Lock a user for work.
-/// It has to be a user which has at least one task ready.
+/// It has to be a user which has at least one task ready, who has waited out their minimum
+/// interval and who has checks left in their daily budget. Of those, the one who has waited
+/// the longest goes first, with their wait multiplied by their weight. So a user of weight 2
+/// gets about twice the checks of the others when everyone has work waiting.
 pub async fn get_next_user(
+    defaults: &QueueDefaults,
     tx: &mut sqlx::Transaction<'_, Postgres>,
 ) -> sqlx::Result<Option<UserQueue>> {
     sqlx::query_as!(
@@ -16,18 +111,52 @@ pub async fn get_next_user(
         JOIN (
             SELECT DISTINCT user_id
             FROM article_queue
-            WHERE done <> true
+            WHERE done <> true AND next_attempt_at <= NOW()
         ) as aq
         ON user_queue.user_id = aq.user_id
-        WHERE last_updated < NOW() - interval '2 seconds'
-        ORDER BY last_updated ASC
+        WHERE last_updated
+                + make_interval(secs => COALESCE(min_interval_secs, $1) / weight) <= NOW()
+            AND (budget_day IS DISTINCT FROM CURRENT_DATE
+                OR checks_today < COALESCE(daily_budget, $2::int, 2147483647))
+        ORDER BY EXTRACT(EPOCH FROM NOW() - last_updated) * weight DESC
         FOR UPDATE OF user_queue SKIP LOCKED
-        LIMIT 1;"
+        LIMIT 1;",
+        defaults.min_interval.as_secs_f64(),
+        defaults.daily_budget,
     )
     .fetch_optional(&mut **tx)
     .await
 }
 
+/// Whether the user could be worked on right away, going by the same rules as get_next_user.
+pub async fn user_has_ready_task(
+    user_id: &i64,
+    defaults: &QueueDefaults,
+    tx: &mut sqlx::Transaction<'_, Postgres>,
+) -> sqlx::Result<bool> {
+    let res = sqlx::query!(
+        r#"SELECT EXISTS (
+            SELECT 1 FROM user_queue
+            WHERE user_id = $1
+                AND last_updated
+                    + make_interval(secs => COALESCE(min_interval_secs, $2) / weight) <= NOW()
+                AND (budget_day IS DISTINCT FROM CURRENT_DATE
+                    OR checks_today < COALESCE(daily_budget, $3::int, 2147483647))
+                AND EXISTS (
+                    SELECT 1 FROM article_queue
+                    WHERE article_queue.user_id = $1 AND NOT done AND next_attempt_at <= NOW()
+                )
+        ) as "ready!";"#,
+        user_id,
+        defaults.min_interval.as_secs_f64(),
+        defaults.daily_budget,
+    )
+    .fetch_one(&mut **tx)
+    .await?;
+    Ok(res.ready)
+}
+
+/// Mark a user as just worked on, counting the check against their daily budget.
 pub async fn update_user_queue(
     id: &i64,
     tx: &mut sqlx::Transaction<'_, Postgres>,
@@ -35,7 +164,9 @@ pub async fn update_user_queue(
     sqlx::query_as!(
         UserQueue,
         "UPDATE user_queue
-        SET last_updated = NOW()
+        SET last_updated = NOW(),
+            checks_today = CASE WHEN budget_day = CURRENT_DATE THEN checks_today + 1 ELSE 1 END,
+            budget_day = CURRENT_DATE
         WHERE id = $1;",
         id,
     )
@@ -44,6 +175,61 @@ pub async fn update_user_queue(
     Ok(())
 }
 
+/// The queue settings in effect for a user.
+pub async fn get_user_queue_settings(
+    user_id: &i64,
+    defaults: &QueueDefaults,
+    conn: &mut PgConnection,
+) -> sqlx::Result<UserQueueSettings> {
+    sqlx::query_as!(
+        UserQueueSettings,
+        r#"
+        SELECT COALESCE(user_queue.min_interval_secs, $2) as "min_interval_secs!",
+            COALESCE(user_queue.weight, 1) as "weight!",
+            COALESCE(user_queue.max_pending, $3) as max_pending,
+            COALESCE(user_queue.daily_budget, $4) as daily_budget,
+            CASE WHEN user_queue.budget_day = CURRENT_DATE
+                THEN user_queue.checks_today ELSE 0 END as "checks_today!",
+            (SELECT COUNT(*) FROM article_queue
+                WHERE article_queue.user_id = $1 AND NOT done) as "pending!"
+        FROM (SELECT $1::bigint as user_id) as u
+        LEFT JOIN user_queue ON user_queue.user_id = u.user_id;
+        "#,
+        user_id,
+        defaults.min_interval.as_secs_f64(),
+        defaults.max_pending,
+        defaults.daily_budget,
+    )
+    .fetch_one(conn)
+    .await
+}
+
+/// Give a user their own queue settings. None leaves a setting to the defaults.
+/// Returns whether the user has a queue to apply them to.
+pub async fn set_user_queue_settings(
+    user_id: &i64,
+    min_interval: Option<std::time::Duration>,
+    weight: f64,
+    max_pending: Option<i64>,
+    daily_budget: Option<i32>,
+    conn: &mut PgConnection,
+) -> sqlx::Result<bool> {
+    let res = sqlx::query!(
+        "UPDATE user_queue
+        SET min_interval_secs = $2, weight = $3, max_pending = $4, daily_budget = $5
+        WHERE user_id = $1;",
+        user_id,
+        min_interval.map(|interval| interval.as_secs_f64()),
+        weight,
+        max_pending,
+        daily_budget,
+    )
+    .execute(conn)
+    .await?;
+    Ok(res.rows_affected() > 0)
+}
+
+#[cfg(test)]
 async fn update_user_queue_to(
     id: &i64,
     interval: &sqlx::postgres::types::PgInterval,
@@ -65,18 +251,101 @@ async fn update_user_queue_to(
 pub async fn insert_tasks(
     user_id: &i64,
     article_ids: &[i64],
+    priority: TaskPriority,
     conn: &mut PgConnection,
-) -> sqlx::Result<()> {
+) -> sqlx::Result<usize> {
+    // Articles that are already pending keep their task, at the higher of the two priorities.
+    // Tasks that are being worked on are locked, and are left as they are instead of waited for.
     sqlx::query!(
-        "INSERT INTO article_queue(user_id, article_id)
-        SELECT $1, *
-        FROM UNNEST($2::bigint[]);",
-        user_id,
+        "UPDATE article_queue SET priority = GREATEST(priority, $2)
+        WHERE id IN (
+            SELECT id FROM article_queue
+            WHERE article_id = ANY($1) AND NOT done
+            FOR UPDATE SKIP LOCKED
+        );",
         article_ids,
+        priority as i16,
     )
     .execute(&mut *conn)
     .await?;
-    Ok(())
+    let res = sqlx::query!(
+        "INSERT INTO article_queue(user_id, article_id, priority)
+        SELECT DISTINCT $1::bigint, article_id, $3::smallint
+        FROM UNNEST($2::bigint[]) as article_id
+        ON CONFLICT (article_id) WHERE NOT done DO NOTHING
+        RETURNING id;",
+        user_id,
+        article_ids,
+        priority as i16,
+    )
+    .fetch_all(&mut *conn)
+    .await?;
+    notify_queue(conn).await?;
+    Ok(res.len())
+}
+
+/// Articles of users with a queue that were not checked in the last `min_interval`,
+/// leaving out those that are queued already, given up on, cancelled or closed to comments.
+pub async fn get_recheck_candidates(
+    min_interval: std::time::Duration,
+    conn: &mut PgConnection,
+) -> sqlx::Result<Vec<RecheckCandidate>> {
+    sqlx::query_as!(
+        RecheckCandidate,
+        r#"
+        SELECT article.id, article.user_id, article.last_checked,
+            GREATEST(
+                article.updated_at,
+                (SELECT MAX(date) FROM comment WHERE comment.article_id = article.id),
+                (SELECT MAX(date) FROM comment_replies
+                    WHERE comment_replies.article_id = article.id)
+            ) as last_active
+        FROM article
+        JOIN user_queue ON user_queue.user_id = article.user_id
+        WHERE NOT article.archived
+            AND (article.last_checked IS NULL
+                OR article.last_checked < NOW() - make_interval(secs => $1))
+            -- Only the latest task counts: one that is queued, gave up or was cancelled keeps
+            -- the article out until it is queued again.
+            AND NOT COALESCE((
+                SELECT NOT done OR dead OR cancelled FROM article_queue
+                WHERE article_queue.article_id = article.id
+                ORDER BY id DESC
+                LIMIT 1
+            ), false)
+            AND NOT EXISTS (
+                SELECT 1 FROM article_content
+                WHERE article_content.article_id = article.id AND comments_disabled
+            );
+        "#,
+        min_interval.as_secs_f64(),
+    )
+    .fetch_all(conn)
+    .await
+}
+
+/// How many tasks each user with a queue has waiting, and how many they may have.
+pub async fn get_pending_tasks(
+    defaults: &QueueDefaults,
+    conn: &mut PgConnection,
+) -> sqlx::Result<HashMap<i64, PendingTasks>> {
+    let pending = sqlx::query_as!(
+        PendingTasks,
+        r#"
+        SELECT user_queue.user_id,
+            (SELECT COUNT(*) FROM article_queue
+                WHERE article_queue.user_id = user_queue.user_id AND NOT done) as "pending!",
+            COALESCE(user_queue.max_pending, $1) as max_pending
+        FROM user_queue;
+        "#,
+        defaults.max_pending,
+    )
+    .fetch_all(conn)
+    .await?;
+    Ok(pending
+        .into_iter()
+        .map(|pending| (pending.user_id, pending))
+        .collect())
 }
 
 /// Get the next task from the task queue
@@ -86,10 +355,10 @@ pub async fn get_next_task(
 ) -> sqlx::Result<Option<ArticleQueueEntry>> {
     sqlx::query_as!(
         ArticleQueueEntry,
-        "SELECT id, user_id, article_id
+        "SELECT id, user_id, article_id, attempts
         FROM article_queue
-        WHERE done=false AND user_id=$1
-        ORDER BY id ASC
+        WHERE done=false AND user_id=$1 AND next_attempt_at <= NOW()
+        ORDER BY priority DESC, id ASC
         FOR UPDATE SKIP LOCKED
         LIMIT 1;",
         user_id
@@ -98,42 +367,227 @@ pub async fn get_next_task(
     .await
 }
 
-/// Return true if there is a pending task for the article
-pub async fn article_is_queued(
+/// Mark a task as done
+pub async fn complete_task(
+    id: i64,
+    error: Option<&str>,
+    tx: &mut sqlx::Transaction<'_, Postgres>,
+) -> sqlx::Result<()> {
+    sqlx::query!(
+        "UPDATE article_queue SET done=true, error=$2, error_msg=$3 WHERE id=$1;",
+        id,
+        error.is_some(),
+        error
+    )
+    .execute(&mut **tx)
+    .await?;
+    Ok(())
+}
+
+/// Record a failed attempt at a task that may well succeed later, such as on a network error.
+/// The task is tried again after `delay`, unless this was its last attempt,
+/// in which case it becomes a dead letter. Returns whether it did.
+pub async fn fail_task_attempt(
+    id: i64,
+    error: &str,
+    delay: std::time::Duration,
+    max_attempts: i32,
+    tx: &mut sqlx::Transaction<'_, Postgres>,
+) -> sqlx::Result<bool> {
+    sqlx::query!(
+        "UPDATE article_queue SET
+            attempts = attempts + 1,
+            last_error = $2,
+            next_attempt_at = NOW() + make_interval(secs => $3),
+            dead = attempts + 1 >= $4,
+            done = attempts + 1 >= $4,
+            error = CASE WHEN attempts + 1 >= $4 THEN TRUE ELSE error END,
+            error_msg = CASE WHEN attempts + 1 >= $4 THEN $2 ELSE error_msg END
+        WHERE id=$1
+        RETURNING dead;",
+        id,
+        error,
+        delay.as_secs_f64(),
+        max_attempts,
+    )
+    .fetch_one(&mut **tx)
+    .await
+    .map(|r| r.dead)
+}
+
+/// Leave a task queued but do not try it again for `delay`, without counting an attempt.
+pub async fn postpone_task(
+    id: i64,
+    delay: std::time::Duration,
+    tx: &mut sqlx::Transaction<'_, Postgres>,
+) -> sqlx::Result<()> {
+    sqlx::query!(
+        "UPDATE article_queue SET next_attempt_at = NOW() + make_interval(secs => $2)
+        WHERE id=$1;",
+        id,
+        delay.as_secs_f64(),
+    )
+    .execute(&mut **tx)
+    .await?;
+    Ok(())
+}
+
+/// Queue a fresh task for an article whose latest task is a dead letter, at the same priority.
+/// Nothing is queued if the article has a task queued already.
+/// Returns whether there was one.
+pub async fn retry_dead_task(
     user_id: &i64,
     article_id: &i64,
     conn: &mut PgConnection,
 ) -> sqlx::Result<bool> {
     let res = sqlx::query!(
-        "SELECT id
-        FROM article_queue
-        WHERE done <> true AND user_id=$1 AND article_id=$2
-        LIMIT 1",
+        "INSERT INTO article_queue(user_id, article_id, priority)
+        SELECT user_id, article_id, priority FROM article_queue
+        WHERE dead AND id = (
+            SELECT id FROM article_queue
+            WHERE user_id=$1 AND article_id=$2
+            ORDER BY id DESC LIMIT 1
+        )
+        ON CONFLICT (article_id) WHERE NOT done DO NOTHING
+        RETURNING id;",
         user_id,
         article_id,
     )
-    .fetch_optional(&mut *conn)
+    .fetch_all(&mut *conn)
     .await?;
-    Ok(res.is_some())
+    notify_queue(conn).await?;
+    Ok(!res.is_empty())
 }
 
-/// Mark a task as done
-pub async fn complete_task(
+/// Cancel the pending task of an article, returning whether there was one.
+/// A task that is being worked on right now is left to finish.
+pub async fn cancel_task(
+    user_id: &i64,
+    article_id: &i64,
+    conn: &mut PgConnection,
+) -> sqlx::Result<bool> {
+    let res = sqlx::query!(
+        "UPDATE article_queue SET done=true, cancelled=true
+        WHERE id IN (
+            SELECT id FROM article_queue
+            WHERE user_id=$1 AND article_id=$2 AND NOT done
+            FOR UPDATE SKIP LOCKED
+        )
+        RETURNING id;",
+        user_id,
+        article_id,
+    )
+    .fetch_all(conn)
+    .await?;
+    Ok(!res.is_empty())
+}
+
+/// Cancel the pending tasks of all articles in a world, returning how many there were.
+/// Tasks that are being worked on right now are left to finish.
+pub async fn cancel_world_tasks(
+    user_id: &i64,
+    world_id: &i64,
+    conn: &mut PgConnection,
+) -> sqlx::Result<usize> {
+    let res = sqlx::query!(
+        "UPDATE article_queue SET done=true, cancelled=true
+        WHERE id IN (
+            SELECT article_queue.id FROM article_queue
+            JOIN article ON article.id = article_queue.article_id
+            WHERE article_queue.user_id=$1 AND article.world_id=$2 AND NOT done
+            FOR UPDATE OF article_queue SKIP LOCKED
+        )
+        RETURNING id;",
+        user_id,
+        world_id,
+    )
+    .fetch_all(conn)
+    .await?;
+    Ok(res.len())
+}
+
+/// Record non-fatal problems encountered while working on a task
+pub async fn set_task_warnings(
     id: i64,
-    error: Option<&str>,
+    warnings: &[String],
     tx: &mut sqlx::Transaction<'_, Postgres>,
 ) -> sqlx::Result<()> {
+    let warning_msg = if warnings.is_empty() {
+        None
+    } else {
+        Some(warnings.join("\n"))
+    };
     sqlx::query!(
-        "UPDATE article_queue SET done=true, error=$2, error_msg=$3 WHERE id=$1;",
+        "UPDATE article_queue SET warning_msg=$2 WHERE id=$1;",
         id,
-        error.is_some(),
-        error
+        warning_msg,
     )
     .execute(&mut **tx)
     .await?;
     Ok(())
 }
 
+/// How many tasks are pruned in one statement, to keep the locks short.
+const PRUNE_BATCH: i64 = 5000;
+
+/// Remove finished tasks that were queued more than `keep` ago from article_queue, adding them
+/// to the counters in article_queue_history. The latest task of every article stays, so its
+/// status can still be shown. Returns how many tasks were removed.
+pub async fn prune_queue_history(
+    keep: std::time::Duration,
+    conn: &mut PgConnection,
+) -> sqlx::Result<i64> {
+    let mut pruned = 0;
+    loop {
+        let res = sqlx::query!(
+            r#"
+            WITH pruned AS (
+                DELETE FROM article_queue
+                WHERE id IN (
+                    SELECT id FROM article_queue AS old
+                    WHERE done AND queued_at < NOW() - make_interval(secs => $1)
+                        AND EXISTS (
+                            SELECT 1 FROM article_queue AS newer
+                            WHERE newer.article_id = old.article_id AND newer.id > old.id
+                        )
+                    LIMIT $2
+                    FOR UPDATE SKIP LOCKED
+                )
+                RETURNING article_id, user_id, error, cancelled
+            ), counts AS (
+                SELECT article_id, MAX(user_id) as user_id, COUNT(*) as entries,
+                    COUNT(*) as done_count,
+                    COUNT(*) FILTER (WHERE error IS TRUE) as errored_count,
+                    COUNT(*) FILTER (WHERE cancelled) as cancelled_count
+                FROM pruned
+                GROUP BY article_id
+            ), summed AS (
+                INSERT INTO article_queue_history(
+                    article_id, user_id, entries, done_count, errored_count, cancelled_count
+                )
+                SELECT article_id, user_id, entries, done_count, errored_count, cancelled_count
+                FROM counts
+                ON CONFLICT (article_id) DO UPDATE SET
+                    entries = article_queue_history.entries + excluded.entries,
+                    done_count = article_queue_history.done_count + excluded.done_count,
+                    errored_count = article_queue_history.errored_count + excluded.errored_count,
+                    cancelled_count =
+                        article_queue_history.cancelled_count + excluded.cancelled_count
+            )
+            SELECT COALESCE(SUM(entries), 0)::bigint as "pruned!" FROM counts;
+            "#,
+            keep.as_secs_f64(),
+            PRUNE_BATCH,
+        )
+        .fetch_one(&mut *conn)
+        .await?;
+        pruned += res.pruned;
+        if res.pruned < PRUNE_BATCH {
+            return Ok(pruned);
+        }
+    }
+}
+
 #[derive(FromRow)]
 struct ArticleQueueInfo {
     queue_length: i64,
@@ -156,10 +610,9 @@ pub async fn get_queue_length(conn: &mut PgConnection) -> sqlx::Result<i64> {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::db::article::register_article;
-    use crate::db::schema::WorldInsert;
+    use crate::db::article::{get_articles_and_status, register_article, set_article_checked_time};
+    use crate::db::test_queries::add_test_article;
     use crate::db::user::{get_user_id_or_insert, insert_user_queue};
-    use crate::db::world::{get_world, upsert_worlds};
     use sqlx::postgres::types::PgInterval;
     use sqlx::{Acquire, PgPool};
 
@@ -170,8 +623,8 @@ mod tests {
         // Insert two users into the db.
         let user1 = get_user_id_or_insert(&mut conn, "key1", "user1", "id1").await?;
         insert_user_queue(&mut conn, &user1.id).await?;
-        let user2 = get_user_id_or_insert(&mut conn, "key2", "user2", "id2").await?;
-        insert_user_queue(&mut conn, &user2.id).await?;
+        let (user2, _, article_id) = add_test_article(&mut *conn, "user2", "myurl").await?;
+        insert_user_queue(&mut conn, &user2).await?;
         // Make it so user1 has an earlier last update time than user1
         update_user_queue_to(
             &user1.id,
@@ -184,7 +637,7 @@ mod tests {
         )
         .await?;
         update_user_queue_to(
-            &user2.id,
+            &user2,
             &PgInterval {
                 months: 0,
                 days: 1,
@@ -194,26 +647,338 @@ mod tests {
         )
         .await?;
         // Insert work for user 2
-        let worlds = upsert_worlds(
-            &mut conn,
-            &user2.id,
-            vec![WorldInsert {
-                worldanvil_id: "worldid".to_string(),
-                name: "testworld".to_string(),
-            }],
-        )
-        .await?;
-        let article_id =
-            register_article(user2.id, worlds[0], "myurl", "mytitle", &mut conn).await?;
-        insert_tasks(&user2.id, &[article_id], &mut conn).await?;
+        insert_tasks(&user2, &[article_id], TaskPriority::Bulk, &mut conn).await?;
 
         // Finally, attempt to select a user for work.
         // It should select user 2.
         let mut tx = conn.begin().await?;
-        let task = get_next_user(&mut tx).await?;
+        let task = get_next_user(&QueueDefaults::default(), &mut tx).await?;
         let task_user_id = task.map(|t| t.user_id);
-        assert_eq!(task_user_id, Some(user2.id));
+        assert_eq!(task_user_id, Some(user2));
+
+        Ok(())
+    }
+
+    /// Failed attempts push a task back until it runs out of attempts, and a retry queues it again.
+    #[sqlx::test]
+    async fn test_task_retries(pool: PgPool) -> anyhow::Result<()> {
+        let mut conn = pool.acquire().await?;
+        let (user, _, article_id) = add_test_article(&mut *conn, "user1", "myurl").await?;
+        insert_tasks(&user, &[article_id], TaskPriority::Bulk, &mut conn).await?;
+
+        let mut tx = conn.begin().await?;
+        let task = get_next_task(&user, &mut tx).await?.unwrap();
+        let delay = std::time::Duration::from_secs(60);
+        assert!(!fail_task_attempt(task.id, "timed out", delay, 2, &mut tx).await?);
+        // The task waits out its backoff.
+        assert!(get_next_task(&user, &mut tx).await?.is_none());
+        assert_eq!(get_queue_length(&mut tx).await?, 1);
+        assert!(fail_task_attempt(task.id, "timed out again", delay, 2, &mut tx).await?);
+        assert_eq!(get_queue_length(&mut tx).await?, 0);
+
+        assert!(retry_dead_task(&user, &article_id, &mut tx).await?);
+        let task = get_next_task(&user, &mut tx).await?.unwrap();
+        assert_eq!(task.attempts, 0);
+        // The fresh task is queued, so there is nothing to retry.
+        assert!(!retry_dead_task(&user, &article_id, &mut tx).await?);
+        // Even a dead task that ended after the pending one was queued does not clash with it.
+        sqlx::query!(
+            "INSERT INTO article_queue(user_id, article_id, done, dead) VALUES ($1, $2, true, true);",
+            user,
+            article_id,
+        )
+        .execute(&mut *tx)
+        .await?;
+        assert!(!retry_dead_task(&user, &article_id, &mut tx).await?);
+
+        // Only the latest of several dead tasks comes back.
+        assert!(fail_task_attempt(task.id, "gone", delay, 1, &mut tx).await?);
+        insert_tasks(&user, &[article_id], TaskPriority::Bulk, &mut tx).await?;
+        let task = get_next_task(&user, &mut tx).await?.unwrap();
+        assert!(fail_task_attempt(task.id, "gone", delay, 1, &mut tx).await?);
+        assert!(retry_dead_task(&user, &article_id, &mut tx).await?);
+        assert_eq!(get_queue_length(&mut tx).await?, 1);
+        Ok(())
+    }
+
+    /// Only articles of queued users that are neither waiting nor recently checked are candidates.
+    #[sqlx::test]
+    async fn test_recheck_candidates(pool: PgPool) -> anyhow::Result<()> {
+        let mut conn = pool.acquire().await?;
+        let (user, world, first) = add_test_article(&mut *conn, "user1", "unchecked").await?;
+        insert_user_queue(&mut conn, &user).await?;
+        let mut article_ids = vec![first];
+        for url in ["queued", "checked", "dead", "recovered"] {
+            article_ids.push(register_article(user, world, url, url, &mut conn).await?);
+        }
+        // Both gave up once, but the second one was checked fine after that.
+        let delay = std::time::Duration::ZERO;
+        for (article_id, recovers) in [(article_ids[3], false), (article_ids[4], true)] {
+            let mut tx = conn.begin().await?;
+            insert_tasks(&user, &[article_id], TaskPriority::Bulk, &mut tx).await?;
+            let task = get_next_task(&user, &mut tx).await?.unwrap();
+            fail_task_attempt(task.id, "gone", delay, 1, &mut tx).await?;
+            if recovers {
+                insert_tasks(&user, &[article_id], TaskPriority::Bulk, &mut tx).await?;
+                let task = get_next_task(&user, &mut tx).await?.unwrap();
+                complete_task(task.id, None, &mut tx).await?;
+            }
+            tx.commit().await?;
+        }
+
+        insert_tasks(&user, &article_ids[1..2], TaskPriority::Bulk, &mut conn).await?;
+        set_article_checked_time(&user, &article_ids[2], &mut conn).await?;
+
+        let hour = std::time::Duration::from_secs(3600);
+        let candidates = get_recheck_candidates(hour, &mut conn).await?;
+        let mut ids: Vec<_> = candidates.iter().map(|candidate| candidate.id).collect();
+        ids.sort();
+        assert_eq!(ids, [article_ids[0], article_ids[4]]);
+        assert_eq!(candidates[0].last_active, None);
+        let candidates = get_recheck_candidates(std::time::Duration::ZERO, &mut conn).await?;
+        assert_eq!(candidates.len(), 3);
+        let pending = get_pending_tasks(&QueueDefaults::default(), &mut conn).await?;
+        assert_eq!(pending[&user].pending, 1);
+        Ok(())
+    }
+
+    /// Queueing work wakes up listeners, and a user who was just worked on has to wait.
+    #[sqlx::test]
+    async fn test_queue_notifications(pool: PgPool) -> anyhow::Result<()> {
+        let mut listener = sqlx::postgres::PgListener::connect_with(&pool).await?;
+        listener.listen(QUEUE_CHANNEL).await?;
+        let mut conn = pool.acquire().await?;
+        let (user, _, article_id) = add_test_article(&mut *conn, "user1", "myurl").await?;
+        insert_user_queue(&mut conn, &user).await?;
+        assert_eq!(
+            time_until_next_task(&QueueDefaults::default(), &mut conn).await?,
+            None
+        );
+
+        insert_tasks(&user, &[article_id], TaskPriority::Bulk, &mut conn).await?;
+        let wait = std::time::Duration::from_secs(5);
+        let notification = tokio::time::timeout(wait, listener.recv()).await??;
+        assert_eq!(notification.channel(), QUEUE_CHANNEL);
+        // The user can be worked on right away.
+        assert_eq!(
+            time_until_next_task(&QueueDefaults::default(), &mut conn).await?,
+            None
+        );
+        let defaults = QueueDefaults::default();
+        let mut tx = conn.begin().await?;
+        assert!(user_has_ready_task(&user, &defaults, &mut tx).await?);
+        let user_queue = get_next_user(&defaults, &mut tx).await?.unwrap();
+        update_user_queue(&user_queue.id, &mut tx).await?;
+        // Nobody needs waking up while the user cools down.
+        assert!(!user_has_ready_task(&user, &defaults, &mut tx).await?);
+        tx.commit().await?;
+        let wait = time_until_next_task(&QueueDefaults::default(), &mut conn).await?;
+        assert!(wait.is_some_and(|wait| wait.as_secs_f64() <= 2.0));
+        Ok(())
+    }
+
+    /// Enqueueing an article twice keeps one task, which goes ahead if asked for more urgently.
+    #[sqlx::test]
+    async fn test_task_priorities(pool: PgPool) -> anyhow::Result<()> {
+        let mut conn = pool.acquire().await?;
+        let (user, world, first) = add_test_article(&mut *conn, "user1", "first").await?;
+        let mut article_ids = vec![first];
+        for url in ["second", "third"] {
+            article_ids.push(register_article(user, world, url, url, &mut conn).await?);
+        }
+        let bulk = TaskPriority::Bulk;
+        assert_eq!(insert_tasks(&user, &article_ids, bulk, &mut conn).await?, 3);
+        assert_eq!(insert_tasks(&user, &article_ids, bulk, &mut conn).await?, 0);
+        let interactive = TaskPriority::Interactive;
+        let third = &article_ids[2..];
+        assert_eq!(insert_tasks(&user, third, interactive, &mut conn).await?, 0);
+        // Queueing in bulk again does not lower the priority.
+        assert_eq!(insert_tasks(&user, third, bulk, &mut conn).await?, 0);
+        assert_eq!(get_queue_length(&mut conn).await?, 3);
+
+        let mut tx = conn.begin().await?;
+        let task = get_next_task(&user, &mut tx).await?.unwrap();
+        assert_eq!(task.article_id, article_ids[2]);
+        complete_task(task.id, None, &mut tx).await?;
+        let task = get_next_task(&user, &mut tx).await?.unwrap();
+        assert_eq!(task.article_id, article_ids[0]);
+        // A finished task does not stop the article from being queued again.
+        assert_eq!(insert_tasks(&user, third, bulk, &mut tx).await?, 1);
+        // A task that is being worked on is neither waited for nor changed.
+        let mut other = pool.acquire().await?;
+        let queued = tokio::time::timeout(
+            std::time::Duration::from_secs(5),
+            insert_tasks(&user, &article_ids[..1], interactive, &mut other),
+        )
+        .await??;
+        assert_eq!(queued, 0);
+        Ok(())
+    }
+
+    /// Weights decide who goes first, and users who used up their daily budget wait.
+    #[sqlx::test]
+    async fn test_user_queue_settings(pool: PgPool) -> anyhow::Result<()> {
+        let mut conn = pool.acquire().await?;
+        let mut users = vec![];
+        for name in ["user1", "user2"] {
+            let (user, _, article_id) = add_test_article(&mut *conn, name, name).await?;
+            insert_user_queue(&mut conn, &user).await?;
+            insert_tasks(&user, &[article_id], TaskPriority::Bulk, &mut conn).await?;
+            users.push(user);
+        }
+        let seconds = |secs: i64| PgInterval {
+            months: 0,
+            days: 0,
+            microseconds: secs * 1_000_000,
+        };
+        update_user_queue_to(&users[0], &seconds(20), &mut conn).await?;
+        update_user_queue_to(&users[1], &seconds(10), &mut conn).await?;
+        let defaults = QueueDefaults::default();
+
+        // The user who waited longer goes first, unless the other one weighs more.
+        let mut tx = conn.begin().await?;
+        let next = get_next_user(&defaults, &mut tx).await?.unwrap();
+        assert_eq!(next.user_id, users[0]);
+        tx.rollback().await?;
+        assert!(set_user_queue_settings(&users[1], None, 3.0, Some(5), Some(1), &mut conn).await?);
+        let mut tx = conn.begin().await?;
+        let next = get_next_user(&defaults, &mut tx).await?.unwrap();
+        assert_eq!(next.user_id, users[1]);
+        update_user_queue(&next.id, &mut tx).await?;
+        tx.commit().await?;
 
+        // Their one check for the day is spent, however long they wait.
+        update_user_queue_to(&users[1], &seconds(60), &mut conn).await?;
+        let mut tx = conn.begin().await?;
+        let next = get_next_user(&defaults, &mut tx).await?.unwrap();
+        assert_eq!(next.user_id, users[0]);
+        tx.rollback().await?;
+        let settings = get_user_queue_settings(&users[1], &defaults, &mut conn).await?;
+        assert_eq!(
+            settings,
+            UserQueueSettings {
+                min_interval_secs: 2.0,
+                weight: 3.0,
+                max_pending: Some(5),
+                daily_budget: Some(1),
+                checks_today: 1,
+                pending: 1,
+            }
+        );
+        assert_eq!(settings.room(), Some(4));
+        let settings = get_user_queue_settings(&users[0], &defaults, &mut conn).await?;
+        assert_eq!(settings.room(), None);
+        Ok(())
+    }
+
+    /// Cancelled tasks are done but not checked, and tasks being worked on are left alone.
+    #[sqlx::test]
+    async fn test_cancel_tasks(pool: PgPool) -> anyhow::Result<()> {
+        let mut conn = pool.acquire().await?;
+        let (user, world, first) = add_test_article(&mut *conn, "user1", "first").await?;
+        insert_user_queue(&mut conn, &user).await?;
+        let other_world = sqlx::query_scalar!(
+            "INSERT INTO world(user_id, worldanvil_id, name)
+            VALUES ($1, 'otherworldid', 'otherworld') RETURNING id;",
+            user,
+        )
+        .fetch_one(&mut *conn)
+        .await?;
+        let mut article_ids = vec![first];
+        for url in ["second", "third"] {
+            article_ids.push(register_article(user, world, url, url, &mut conn).await?);
+        }
+        let other = register_article(user, other_world, "other", "other", &mut conn).await?;
+        insert_tasks(&user, &article_ids, TaskPriority::Bulk, &mut conn).await?;
+        insert_tasks(&user, &[other], TaskPriority::Bulk, &mut conn).await?;
+
+        assert!(cancel_task(&user, &article_ids[0], &mut conn).await?);
+        assert!(!cancel_task(&user, &article_ids[0], &mut conn).await?);
+        let articles = get_articles_and_status(&user, &world, None, &mut *conn).await?;
+        let cancelled: Vec<_> = articles
+            .iter()
+            .filter(|article| article.status.as_ref().is_some_and(|s| s.cancelled))
+            .map(|article| article.article_id)
+            .collect();
+        assert_eq!(cancelled, [article_ids[0]]);
+        // The scheduler does not bring it back.
+        let candidates = get_recheck_candidates(std::time::Duration::ZERO, &mut conn).await?;
+        assert!(candidates.is_empty());
+
+        // The task being worked on finishes, the other pending one in the world is cancelled.
+        let mut worker = pool.begin().await?;
+        let task = get_next_task(&user, &mut worker).await?.unwrap();
+        assert_eq!(task.article_id, article_ids[1]);
+        assert_eq!(cancel_world_tasks(&user, &world, &mut conn).await?, 1);
+        complete_task(task.id, None, &mut worker).await?;
+        worker.commit().await?;
+        assert_eq!(get_queue_length(&mut conn).await?, 1);
+        Ok(())
+    }
+
+    /// Old finished tasks are summed up, keeping the latest task of every article.
+    #[sqlx::test]
+    async fn test_prune_queue_history(pool: PgPool) -> anyhow::Result<()> {
+        let mut conn = pool.acquire().await?;
+        let (user, world, first) = add_test_article(&mut *conn, "user1", "requeued").await?;
+        let mut article_ids = vec![first];
+        for url in ["checked once", "cancelled"] {
+            article_ids.push(register_article(user, world, url, url, &mut conn).await?);
+        }
+        let work_through = |article_id: i64, error: Option<&'static str>| {
+            let pool = pool.clone();
+            async move {
+                let mut tx = pool.begin().await?;
+                insert_tasks(&user, &[article_id], TaskPriority::Bulk, &mut tx).await?;
+                let task = get_next_task(&user, &mut tx).await?.unwrap();
+                complete_task(task.id, error, &mut tx).await?;
+                tx.commit().await?;
+                anyhow::Ok(())
+            }
+        };
+        work_through(article_ids[0], Some("failed")).await?;
+        work_through(article_ids[0], None).await?;
+        insert_tasks(&user, &article_ids[..1], TaskPriority::Bulk, &mut conn).await?;
+        work_through(article_ids[1], None).await?;
+        insert_tasks(&user, &article_ids[2..], TaskPriority::Bulk, &mut conn).await?;
+        cancel_task(&user, &article_ids[2], &mut conn).await?;
+        work_through(article_ids[2], None).await?;
+
+        let month = std::time::Duration::from_secs(30 * 86400);
+        assert_eq!(prune_queue_history(month, &mut conn).await?, 0);
+        sqlx::query!("UPDATE article_queue SET queued_at = NOW() - interval '60 days';")
+            .execute(&mut *conn)
+            .await?;
+        assert_eq!(prune_queue_history(month, &mut conn).await?, 3);
+        assert_eq!(prune_queue_history(month, &mut conn).await?, 0);
+
+        let history = sqlx::query!(
+            "SELECT article_id, entries, done_count, errored_count, cancelled_count
+            FROM article_queue_history
+            ORDER BY article_id;"
+        )
+        .fetch_all(&mut *conn)
+        .await?;
+        let counts: Vec<_> = history
+            .iter()
+            .map(|row| {
+                (
+                    row.article_id,
+                    row.entries,
+                    row.done_count,
+                    row.errored_count,
+                    row.cancelled_count,
+                )
+            })
+            .collect();
+        assert_eq!(
+            counts,
+            [(article_ids[0], 2, 2, 1, 0), (article_ids[2], 1, 1, 0, 1)]
+        );
+        // Every article still has its latest task.
+        assert_eq!(get_queue_length(&mut conn).await?, 1);
+        let articles = get_articles_and_status(&user, &world, None, &mut *conn).await?;
+        assert!(articles.iter().all(|article| article.status.is_some()));
         Ok(())
     }
 }
@@ -1,7 +1,10 @@
 use crate::dateutil::{date_as_human_friendly, date_option_as_human_friendly};
+use crate::parser::content::normalize_mention;
 use serde::Serialize;
 use sqlx;
 use sqlx::FromRow;
+use std::fmt;
+use std::str::FromStr;
 use time::OffsetDateTime;
 
 /// A user seen in comments.
@@ -28,6 +31,8 @@ pub struct CommentaterUser {
     pub worldanvil_id: String,
     pub last_seen: OffsetDateTime,
     pub api_key: String,
+    /// IANA name of the timezone WorldAnvil shows this user's dates in.
+    pub timezone: Option<String>,
 }
 
 #[derive(FromRow, Serialize)]
@@ -36,11 +41,85 @@ pub struct World {
     pub user_id: i64,
     pub worldanvil_id: String,
     pub name: String,
+    /// The world homepage.
+    pub url: Option<String>,
 }
 
 pub struct WorldInsert {
     pub worldanvil_id: String,
     pub name: String,
+    pub url: Option<String>,
+}
+
+/// The kinds of WorldAnvil page readers can leave comments on.
+/// They are all tracked in the article table.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PageKind {
+    Article,
+    /// The world homepage.
+    World,
+    Timeline,
+    Map,
+    Manuscript,
+}
+
+impl PageKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PageKind::Article => "article",
+            PageKind::World => "world",
+            PageKind::Timeline => "timeline",
+            PageKind::Map => "map",
+            PageKind::Manuscript => "manuscript",
+        }
+    }
+}
+
+impl fmt::Display for PageKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[derive(thiserror::Error, Debug)]
+#[error("unknown page kind '{0}'")]
+pub struct UnknownPageKind(String);
+
+impl FromStr for PageKind {
+    type Err = UnknownPageKind;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "article" => Ok(PageKind::Article),
+            "world" => Ok(PageKind::World),
+            "timeline" => Ok(PageKind::Timeline),
+            "map" => Ok(PageKind::Map),
+            "manuscript" => Ok(PageKind::Manuscript),
+            _ => Err(UnknownPageKind(s.to_string())),
+        }
+    }
+}
+
+/// How urgently a task is wanted. Pending tasks of a user are worked on highest priority first.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum TaskPriority {
+    /// Queued along with every other article of a world.
+    Bulk = 0,
+    /// Queued by the recheck scheduler.
+    Scheduled = 1,
+    /// Asked for by hand, for a single article.
+    Interactive = 2,
+}
+
+/// A commentable page found on WorldAnvil, to be registered for tracking.
+pub struct PageInsert {
+    pub kind: PageKind,
+    pub worldanvil_id: String,
+    pub title: String,
+    pub url: String,
+    /// When WorldAnvil says the page was last edited.
+    pub updated_at: Option<OffsetDateTime>,
 }
 
 #[derive(FromRow, Serialize)]
@@ -48,9 +127,17 @@ pub struct Article {
     pub id: i64,
     pub user_id: i64,
     pub world_id: i64,
+    /// A PageKind.
+    pub kind: String,
+    pub worldanvil_id: Option<String>,
     pub url: String,
     #[serde(with = "time::serde::iso8601::option")]
     pub last_checked: Option<OffsetDateTime>,
+    /// Validators of the last fetch of the page.
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+    /// A hash of the comments as of the last check that read all of them.
+    pub comments_hash: Option<String>,
 }
 
 #[derive(FromRow, Serialize)]
@@ -78,39 +165,139 @@ pub struct Comment {
     pub id: i64,
     pub user_id: i64,
     pub author_id: Option<i64>,
+    pub author_name: Option<String>,
     pub article_id: i64,
+    pub identity: String,
     pub content: String,
+    pub content_html: String,
     #[serde(serialize_with = "date_as_human_friendly")]
     pub date: OffsetDateTime,
     pub starred: bool,
     pub deleted: bool,
+    pub answered: bool,
+    /// The comment is no longer on the page.
+    pub removed: bool,
+    /// Lowercased names of the users mentioned in the comment.
+    pub mentions: Vec<String>,
+    /// The comment asks a question.
+    pub question: bool,
 }
 
 impl Comment {
-    /// The unique key for this comment
-    pub fn key(&self) -> Option<(i64, OffsetDateTime)> {
-        self.author_id.map(|author_id| (author_id, self.date))
+    /// The unique key for this comment within its article
+    pub fn key(&self) -> &str {
+        &self.identity
     }
 }
 
+/// Article-level information shown on the article page, stored with the article content.
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct ArticleMetadata {
+    pub author: Option<String>,
+    pub word_count: Option<i32>,
+    /// Favorites, likes or hearts, whatever WorldAnvil calls them at the moment.
+    pub likes: Option<i32>,
+    pub views: Option<i32>,
+    pub tags: Vec<String>,
+    pub article_type: Option<String>,
+    pub comments_disabled: bool,
+}
+
 /// A comment struct for inserting into the db.
 pub struct CommentInsert {
     pub user_id: i64,
-    pub author_id: i64,
+    pub author_id: Option<i64>,
     pub article_id: i64,
+    pub identity: String,
     pub content: String,
+    pub content_html: String,
+    pub mentions: Vec<String>,
+    pub links: Vec<String>,
+    pub question: bool,
     pub date: OffsetDateTime,
+    /// The date was relative on the page, so the stored one is kept if there is one.
+    pub approximate_date: bool,
+    pub answered: bool,
 }
 
-pub struct CommentReplies {
+#[derive(FromRow, Serialize)]
+pub struct CommentReply {
     pub id: i64,
     pub user_id: i64,
+    pub author_id: Option<i64>,
+    pub author_name: Option<String>,
     pub article_id: i64,
     pub parent: i64,
+    pub identity: String,
     pub content: String,
+    pub content_html: String,
+    #[serde(serialize_with = "date_as_human_friendly")]
     pub date: OffsetDateTime,
     pub starred: bool,
     pub deleted: bool,
+    pub removed: bool,
+    /// Lowercased names of the users mentioned in the comment.
+    pub mentions: Vec<String>,
+    /// The comment asks a question.
+    pub question: bool,
+}
+
+/// A reply struct for inserting into the db. The parent is supplied separately.
+pub struct CommentReplyInsert {
+    pub user_id: i64,
+    pub author_id: Option<i64>,
+    pub article_id: i64,
+    pub identity: String,
+    pub content: String,
+    pub content_html: String,
+    pub mentions: Vec<String>,
+    pub links: Vec<String>,
+    pub question: bool,
+    pub date: OffsetDateTime,
+    /// The date was relative on the page, so the stored one is kept if there is one.
+    pub approximate_date: bool,
+}
+
+/// Whether a comment mentions the user or asks a question.
+fn addresses_user(mentions: &[String], question: bool, user_name: Option<&str>) -> bool {
+    question || user_name.is_some_and(|name| mentions.contains(&normalize_mention(name)))
+}
+
+impl Comment {
+    pub fn addresses_user(&self, user_name: Option<&str>) -> bool {
+        addresses_user(&self.mentions, self.question, user_name)
+    }
+}
+
+impl CommentReply {
+    pub fn addresses_user(&self, user_name: Option<&str>) -> bool {
+        addresses_user(&self.mentions, self.question, user_name)
+    }
+}
+
+/// A root comment together with all of its replies.
+#[derive(Serialize)]
+pub struct CommentThread {
+    pub comment: Comment,
+    pub replies: Vec<CommentReply>,
+    /// The thread is unanswered and mentions the user or asks a question, so it comes first.
+    pub priority: bool,
+}
+
+impl CommentThread {
+    pub fn new(comment: Comment, replies: Vec<CommentReply>, user_name: Option<&str>) -> Self {
+        let priority = !comment.answered
+            && !comment.removed
+            && (comment.addresses_user(user_name)
+                || replies
+                    .iter()
+                    .any(|reply| !reply.removed && reply.addresses_user(user_name)));
+        Self {
+            comment,
+            replies,
+            priority,
+        }
+    }
 }
 
 #[derive(FromRow, Clone)]
@@ -118,6 +305,18 @@ pub struct ArticleQueueEntry {
     pub id: i64,
     pub user_id: i64,
     pub article_id: i64,
+    /// Failed attempts so far.
+    pub attempts: i32,
+}
+
+/// An article that may be due for an automatic recheck.
+#[derive(FromRow, Clone, Debug)]
+pub struct RecheckCandidate {
+    pub id: i64,
+    pub user_id: i64,
+    pub last_checked: Option<OffsetDateTime>,
+    /// The latest of when the article was edited and when it was last commented on.
+    pub last_active: Option<OffsetDateTime>,
 }
 
 #[derive(FromRow)]
@@ -127,47 +326,122 @@ pub struct UserQueue {
     pub last_updated: OffsetDateTime,
 }
 
+/// The queue settings in effect for a user, their own where they have any and the defaults
+/// otherwise, along with how much of their quotas they are using.
+#[derive(FromRow, Serialize, Clone, Debug, PartialEq)]
+pub struct UserQueueSettings {
+    /// The least time between two checks for the user, before their weight is applied.
+    pub min_interval_secs: f64,
+    /// How much more often than others the user gets their turn.
+    pub weight: f64,
+    /// How many tasks the user can have waiting, if limited.
+    pub max_pending: Option<i64>,
+    /// How many checks are made for the user per day, if limited.
+    pub daily_budget: Option<i32>,
+    pub checks_today: i32,
+    /// Tasks the user has waiting.
+    pub pending: i64,
+}
+
+impl UserQueueSettings {
+    /// How many more tasks can be queued for the user in bulk, if limited.
+    pub fn room(&self) -> Option<i64> {
+        self.max_pending.map(|max| (max - self.pending).max(0))
+    }
+}
+
+/// How many tasks a user with a queue has waiting, and how many they may have.
+#[derive(FromRow, Clone, Debug, PartialEq)]
+pub struct PendingTasks {
+    pub user_id: i64,
+    pub pending: i64,
+    pub max_pending: Option<i64>,
+}
+
 #[derive(FromRow, Serialize)]
 pub struct RawArticleAndStatus {
     pub article_id: i64,
+    pub kind: String,
     pub title: String,
     pub url: String,
     pub last_checked: Option<OffsetDateTime>,
+    pub archived: bool,
     pub done: Option<bool>,
     pub error: Option<bool>,
     pub error_msg: Option<String>,
+    pub warning_msg: Option<String>,
+    pub attempts: Option<i32>,
+    pub last_error: Option<String>,
+    pub dead: Option<bool>,
+    pub cancelled: Option<bool>,
     pub unanswered_comments: Option<i64>,
+    pub priority_comments: Option<i64>,
+    pub author: Option<String>,
+    pub word_count: Option<i32>,
+    pub likes: Option<i32>,
+    pub views: Option<i32>,
+    pub tags: Option<Vec<String>>,
+    pub article_type: Option<String>,
+    pub comments_disabled: Option<bool>,
 }
 
 impl RawArticleAndStatus {
     pub fn into_article_and_status(self) -> ArticleAndStatus {
         let RawArticleAndStatus {
             article_id,
+            kind,
             title,
             url,
             last_checked,
+            archived,
             done,
             error,
             error_msg,
+            warning_msg,
+            attempts,
+            last_error,
+            dead,
+            cancelled,
             unanswered_comments,
+            priority_comments,
+            author,
+            word_count,
+            likes,
+            views,
+            tags,
+            article_type,
+            comments_disabled,
         } = self;
         // If done exists, all the others must exist
-        let status = if done.is_some() {
-            Some(ArticleStatus {
-                done: done.unwrap(),
-                error,
-                error_msg,
-            })
-        } else {
-            None
-        };
+        let status = done.map(|done| ArticleStatus {
+            done,
+            error,
+            error_msg,
+            warning_msg,
+            attempts: attempts.unwrap_or(0),
+            last_error,
+            dead: dead.unwrap_or(false),
+            cancelled: cancelled.unwrap_or(false),
+        });
         ArticleAndStatus {
             article_id,
+            kind,
             title,
             url,
             last_checked,
+            archived,
             status,
             unanswered_comments: unanswered_comments.unwrap_or(0),
+            priority_comments: priority_comments.unwrap_or(0),
+            metadata: ArticleMetadata {
+                author,
+                word_count,
+                likes,
+                views,
+                tags: tags.unwrap_or_default(),
+                article_type,
+                comments_disabled: comments_disabled.unwrap_or(false),
+            },
         }
     }
 }
@@ -175,12 +449,18 @@ impl RawArticleAndStatus {
 #[derive(Serialize)]
 pub struct ArticleAndStatus {
     pub article_id: i64,
+    pub kind: String,
     pub title: String,
     pub url: String,
     #[serde(serialize_with = "date_option_as_human_friendly")]
     pub last_checked: Option<OffsetDateTime>,
+    /// The article was deleted on WorldAnvil.
+    pub archived: bool,
     pub status: Option<ArticleStatus>,
     pub unanswered_comments: i64,
+    /// Unanswered threads that mention the user or ask a question.
+    pub priority_comments: i64,
+    pub metadata: ArticleMetadata,
 }
 
 #[derive(Serialize)]
@@ -188,4 +468,12 @@ pub struct ArticleStatus {
     pub done: bool,
     pub error: Option<bool>,
     pub error_msg: Option<String>,
+    pub warning_msg: Option<String>,
+    /// Failed attempts so far, the last of which failed with `last_error`.
+    pub attempts: i32,
+    pub last_error: Option<String>,
+    /// The task ran out of attempts and was given up on.
+    pub dead: bool,
+    /// The user cancelled the task before it was worked on.
+    pub cancelled: bool,
 }
@@ -1,4 +1,8 @@
+use crate::db::article::register_article;
 use crate::db::pgacquire::PgAcquire;
+use crate::db::schema::WorldInsert;
+use crate::db::user::get_user_id_or_insert;
+use crate::db::world::upsert_worlds;
 use std::env;
 
 pub async fn add_test_data<'a, A: PgAcquire<'a>>(conn: A) {
@@ -48,3 +52,26 @@ pub async fn add_test_data<'a, A: PgAcquire<'a>>(conn: A) {
     .await
     .unwrap();
 }
+
+/// Insert a user called `name` with one world and an article at `url` in it.
+/// Returns the ids of the user, the world and the article.
+pub async fn add_test_article<'a, A: PgAcquire<'a>>(
+    conn: A,
+    name: &str,
+    url: &str,
+) -> sqlx::Result<(i64, i64, i64)> {
+    let mut conn = conn.acquire().await?;
+    let user = get_user_id_or_insert(&mut *conn, name, name, name).await?;
+    let worlds = upsert_worlds(
+        &mut *conn,
+        &user.id,
+        vec![WorldInsert {
+            worldanvil_id: format!("{name}-world"),
+            name: "testworld".to_string(),
+            url: None,
+        }],
+    )
+    .await?;
+    let article_id = register_article(user.id, worlds[0], url, "mytitle", &mut *conn).await?;
+    Ok((user.id, worlds[0], article_id))
+}
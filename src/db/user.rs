@@ -15,7 +15,7 @@ pub async fn get_user_id_or_insert<'a, A: PgAcquire<'a>>(
     VALUES($1, $2, $3)
     ON CONFLICT (api_key) DO UPDATE
     SET api_key=$1, display_name=$2, worldanvil_id=$3
-    RETURNING id, display_name, api_key, last_seen, worldanvil_id",
+    RETURNING id, display_name, api_key, last_seen, worldanvil_id, timezone",
         api_key,
         display_name,
         worldanvil_id
@@ -32,7 +32,7 @@ pub async fn get_user<'a, A: PgAcquire<'a>>(
     sqlx::query_as!(
         CommentaterUser,
         "
-        SELECT id, display_name, api_key, last_seen, worldanvil_id
+        SELECT id, display_name, api_key, last_seen, worldanvil_id, timezone
         FROM commentater_user
         WHERE id=$1
         LIMIT 1",
@@ -54,3 +54,19 @@ pub async fn insert_user_queue<'a, A: PgAcquire<'a>>(conn: A, user_id: &i64) ->
     .await?;
     Ok(())
 }
+
+pub async fn set_user_timezone<'a, A: PgAcquire<'a>>(
+    conn: A,
+    user_id: &i64,
+    timezone: Option<&str>,
+) -> sqlx::Result<()> {
+    let mut conn = conn.acquire().await?;
+    sqlx::query!(
+        "UPDATE commentater_user SET timezone=$2 WHERE id=$1;",
+        user_id,
+        timezone,
+    )
+    .execute(&mut *conn)
+    .await?;
+    Ok(())
+}
@@ -5,7 +5,7 @@ pub async fn get_worlds<'a, A: PgAcquire<'a>>(conn: A, user_id: &i64) -> sqlx::R
     let mut conn = conn.acquire().await?;
     sqlx::query_as!(
         World,
-        "SELECT id, user_id, worldanvil_id, name
+        "SELECT id, user_id, worldanvil_id, name, url
         FROM world
         WHERE user_id=$1",
         user_id,
@@ -23,7 +23,7 @@ pub async fn get_world<'a, A: PgAcquire<'a>>(
     sqlx::query_as!(
         World,
         "
-    SELECT id, user_id, worldanvil_id, name
+    SELECT id, user_id, worldanvil_id, name, url
     FROM world
     WHERE user_id=$1 AND id=$2
     LIMIT 1;",
@@ -42,13 +42,16 @@ pub async fn upsert_worlds<'a, A: PgAcquire<'a>>(
     let mut conn = conn.acquire().await?;
     let mut world_ids = Vec::new();
     let mut world_names = Vec::new();
+    let mut world_urls = Vec::new();
     worlds.into_iter().for_each(|world| {
         let WorldInsert {
             worldanvil_id,
             name,
+            url,
         } = world;
         world_ids.push(worldanvil_id);
         world_names.push(name);
+        world_urls.push(url);
     });
     // First delete all worlds not in worlds, then upsert
     sqlx::query!(
@@ -63,15 +66,16 @@ pub async fn upsert_worlds<'a, A: PgAcquire<'a>>(
     .await?;
     let returning = sqlx::query!(
         "
-        INSERT INTO world(user_id, worldanvil_id, name)
-        SELECT $1, * FROM UNNEST($2::text[], $3::text[])
+        INSERT INTO world(user_id, worldanvil_id, name, url)
+        SELECT $1, * FROM UNNEST($2::text[], $3::text[], $4::text[])
         ON CONFLICT (user_id, worldanvil_id) DO UPDATE SET
-            name=EXCLUDED.name
+            name=EXCLUDED.name, url=EXCLUDED.url
         RETURNING id;
         ",
         user_id,
         &world_ids,
         &world_names,
+        &world_urls as _,
     )
     .fetch_all(&mut *conn)
     .await?;
@@ -4,7 +4,7 @@ use std::fs::OpenOptions;
 
 pub mod article_updater;
 pub mod auth;
-mod dateutil;
+pub mod dateutil;
 pub mod db;
 pub mod err;
 pub mod log_config;
@@ -12,17 +12,16 @@ pub mod parser;
 pub mod req;
 pub mod response;
 pub mod routes;
+pub mod scheduler;
 pub mod templates;
+pub mod worker;
 pub mod worldanvil_api;
 
 pub static TEST_USER_ID: i64 = 5;
 pub static TEST_WORLD_ID: i64 = 5;
 
 pub fn setup_logging(file_name: &str) -> anyhow::Result<()> {
-    let log_file = OpenOptions::new()
-        .write(true)
-        .append(true)
-        .open(file_name)?;
+    let log_file = OpenOptions::new().append(true).open(file_name)?;
 
     let debug = envvar("DEBUG").map(|v| !v.is_empty()).unwrap_or(false);
     let log_level = if debug {
@@ -0,0 +1,349 @@
+use itertools::Itertools;
+use lazy_static::lazy_static;
+use regex::Regex;
+use scraper::node::Node;
+use scraper::{ElementRef, Selector};
+use url::Url;
+
+lazy_static! {
+    static ref LINK_SELECTOR: Selector = Selector::parse("a[href]").unwrap();
+    /// An @name that is not part of an email address.
+    static ref MENTION_PATTERN: Regex = Regex::new(r"(?:^|[^\w@])@([\w.-]+)").unwrap();
+    /// A question mark that ends a sentence, possibly inside closing quotes or brackets.
+    static ref QUESTION_PATTERN: Regex = Regex::new(r#"\?["'”’)\]]*(?:\s|$)"#).unwrap();
+    /// A url pasted as text, whose query string is not a question.
+    static ref URL_PATTERN: Regex = Regex::new(r"(?:https?://|www\.)\S+").unwrap();
+}
+
+/// Relative links in comments are relative to WorldAnvil.
+const BASE_URL: &str = "https://www.worldanvil.com/";
+
+/// Elements which are kept in the html, without any of their attributes.
+const ALLOWED_TAGS: &[&str] = &[
+    "p",
+    "br",
+    "strong",
+    "b",
+    "em",
+    "i",
+    "u",
+    "s",
+    "blockquote",
+    "ul",
+    "ol",
+    "li",
+    "code",
+    "pre",
+];
+
+/// Elements which are dropped together with everything inside them.
+const DROPPED_TAGS: &[&str] = &[
+    "script", "style", "iframe", "object", "embed", "form", "input", "button", "img", "svg",
+    "noscript", "template",
+];
+
+/// Elements which start a new paragraph in the plain text rendering.
+const BLOCK_TAGS: &[&str] = &[
+    "p", "div", "ul", "ol", "pre", "h1", "h2", "h3", "h4", "h5", "h6",
+];
+
+/// The body of a comment, both as sanitized html and as plain text.
+#[derive(Debug, PartialEq)]
+pub struct CommentContent {
+    pub html: String,
+    pub text: String,
+    /// Lowercased names of the users the comment mentions.
+    pub mentions: Vec<String>,
+    /// Links to anything but a user profile.
+    pub links: Vec<String>,
+    /// The commenter asks something, outside of any quotes.
+    pub question: bool,
+}
+
+/// Extract the body of a comment from its .comment-box-content node.
+pub fn extract_content(element: &ElementRef) -> CommentContent {
+    let text = normalize_text(&text_children(element));
+    let mut mentions = vec![];
+    let mut links = vec![];
+    for link in element.select(&LINK_SELECTOR) {
+        // Links in signatures are not part of what the commenter wrote.
+        let dropped = link
+            .ancestors()
+            .take_while(|node| node.id() != element.id())
+            .filter_map(ElementRef::wrap)
+            .any(|ancestor| is_dropped(&ancestor));
+        if dropped {
+            continue;
+        }
+        let Some(href) = link.attr("href").and_then(resolve_link) else {
+            continue;
+        };
+        match profile_name(&href) {
+            Some(name) => mentions.push(normalize_mention(&name)),
+            None => links.push(href),
+        }
+    }
+    let own_lines = || text.lines().filter(|line| !line.starts_with('>'));
+    for line in own_lines() {
+        for capture in MENTION_PATTERN.captures_iter(line) {
+            let name = capture[1].trim_end_matches(['.', '-']);
+            if !name.is_empty() {
+                mentions.push(normalize_mention(name));
+            }
+        }
+    }
+    CommentContent {
+        html: sanitize_children(element).trim().to_string(),
+        mentions: mentions.into_iter().unique().collect(),
+        links: links.into_iter().unique().collect(),
+        question: own_lines().any(asks_question),
+        text,
+    }
+}
+
+/// How user names are compared to the mentions of a comment, which are stored this way.
+pub fn normalize_mention(name: &str) -> String {
+    name.to_lowercase()
+}
+
+/// Whether a line of a comment ends a sentence with a question mark, ignoring urls.
+fn asks_question(line: &str) -> bool {
+    QUESTION_PATTERN.is_match(&URL_PATTERN.replace_all(line, ""))
+}
+
+/// The user a link to a WorldAnvil profile page is for.
+fn profile_name(href: &str) -> Option<String> {
+    let url = Url::parse(href).ok()?;
+    if !url.host_str()?.ends_with("worldanvil.com") {
+        return None;
+    }
+    let mut segments = url.path_segments()?;
+    match (segments.next(), segments.next()) {
+        (Some("author"), Some(name)) if !name.is_empty() => Some(name.to_string()),
+        _ => None,
+    }
+}
+
+fn escape_html(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#39;")
+}
+
+/// Signatures are part of the comment markup but not of what the commenter wrote.
+fn is_dropped(element: &ElementRef) -> bool {
+    DROPPED_TAGS.contains(&element.value().name())
+        || element
+            .value()
+            .classes()
+            .any(|class| class == "comment-box-signature")
+}
+
+fn is_spoiler(element: &ElementRef) -> bool {
+    element
+        .value()
+        .classes()
+        .any(|class| class.contains("spoiler"))
+}
+
+/// Resolve a link against WorldAnvil, only allowing http(s) links through.
+fn resolve_link(href: &str) -> Option<String> {
+    let url = Url::parse(BASE_URL).ok()?.join(href.trim()).ok()?;
+    match url.scheme() {
+        "http" | "https" => Some(url.to_string()),
+        _ => None,
+    }
+}
+
+fn sanitize_children(element: &ElementRef) -> String {
+    let mut html = String::new();
+    for child in element.children() {
+        match child.value() {
+            Node::Text(text) => html.push_str(&escape_html(text)),
+            Node::Element(_) => {
+                if let Some(child) = ElementRef::wrap(child) {
+                    html.push_str(&sanitize_element(&child));
+                }
+            }
+            _ => {}
+        }
+    }
+    html
+}
+
+fn sanitize_element(element: &ElementRef) -> String {
+    if is_dropped(element) {
+        return String::new();
+    }
+    let name = element.value().name();
+    if name == "br" {
+        return "<br>".to_string();
+    }
+    let inner = sanitize_children(element);
+    if name == "a" {
+        return match element.attr("href").and_then(resolve_link) {
+            Some(href) => format!(
+                r#"<a href="{}" rel="nofollow noopener" target="_blank">{inner}</a>"#,
+                escape_html(&href)
+            ),
+            None => inner,
+        };
+    }
+    if is_spoiler(element) {
+        return format!(r#"<span class="spoiler">{inner}</span>"#);
+    }
+    if ALLOWED_TAGS.contains(&name) {
+        format!("<{name}>{inner}</{name}>")
+    } else {
+        inner
+    }
+}
+
+fn text_children(element: &ElementRef) -> String {
+    let mut text = String::new();
+    for child in element.children() {
+        match child.value() {
+            // Newlines in the markup are just whitespace.
+            Node::Text(t) => text.push_str(&t.replace(char::is_whitespace, " ")),
+            Node::Element(_) => {
+                if let Some(child) = ElementRef::wrap(child) {
+                    text.push_str(&text_element(&child));
+                }
+            }
+            _ => {}
+        }
+    }
+    text
+}
+
+fn text_element(element: &ElementRef) -> String {
+    if is_dropped(element) {
+        return String::new();
+    }
+    let name = element.value().name();
+    if name == "br" {
+        return "\n".to_string();
+    }
+    let inner = text_children(element);
+    match name {
+        "blockquote" => {
+            let quoted = normalize_text(&inner)
+                .lines()
+                .map(|line| format!("> {line}"))
+                .join("\n");
+            format!("\n\n{quoted}\n\n")
+        }
+        "li" => format!("\n- {inner}\n"),
+        _ if BLOCK_TAGS.contains(&name) => format!("\n\n{inner}\n\n"),
+        _ => inner,
+    }
+}
+
+/// Collapse whitespace within lines and keep at most one blank line between paragraphs.
+fn normalize_text(raw: &str) -> String {
+    let mut lines: Vec<String> = vec![];
+    for line in raw.split('\n') {
+        let line = line.split_whitespace().join(" ");
+        // Blank lines are only kept directly after a non-blank line.
+        if !line.is_empty() || lines.last().is_some_and(|last| !last.is_empty()) {
+            lines.push(line);
+        }
+    }
+    if lines.last().is_some_and(String::is_empty) {
+        lines.pop();
+    }
+    lines.join("\n")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use scraper::{Html, Selector};
+
+    fn content_of(body: &str) -> CommentContent {
+        let fragment =
+            Html::parse_fragment(&format!(r#"<div class="comment-box-content">{body}</div>"#));
+        let selector = Selector::parse(".comment-box-content").unwrap();
+        extract_content(&fragment.select(&selector).next().unwrap())
+    }
+
+    #[test]
+    fn test_extract_content() {
+        let content = content_of(
+            r#"
+            <p>First <strong>bold</strong> paragraph,
+               about <a class="article-link" href="/w/solaris-nnie/a/chewpaper-material">Chewpaper</a>.</p>
+            <p>Hey <a class="user-tag" href="/author/nnie">@nnie</a><br>second line</p>
+            <blockquote><p>A quote</p></blockquote>
+            <span class="spoiler">hidden</span>
+            <script>alert("hi")</script>
+            <div class="comment-box-signature">My <a href="/w/my-world">signature</a></div>
+            "#,
+        );
+        assert_eq!(
+            content.text,
+            "First bold paragraph, about Chewpaper.\n\nHey @nnie\nsecond line\n\n> A quote\n\nhidden"
+        );
+        assert_eq!(content.mentions, ["nnie"]);
+        assert_eq!(
+            content.links,
+            ["https://www.worldanvil.com/w/solaris-nnie/a/chewpaper-material"]
+        );
+        assert!(!content.question);
+        assert_eq!(
+            content.html,
+            concat!(
+                "<p>First <strong>bold</strong> paragraph,\n               about ",
+                r#"<a href="https://www.worldanvil.com/w/solaris-nnie/a/chewpaper-material" rel="nofollow noopener" target="_blank">Chewpaper</a>.</p>"#,
+                "\n            ",
+                r#"<p>Hey <a href="https://www.worldanvil.com/author/nnie" rel="nofollow noopener" target="_blank">@nnie</a><br>second line</p>"#,
+                "\n            <blockquote><p>A quote</p></blockquote>\n            ",
+                r#"<span class="spoiler">hidden</span>"#,
+            )
+        );
+    }
+
+    #[test]
+    fn test_extract_mentions_and_questions() {
+        let content = content_of(
+            r#"
+            <p>@Tyrdal and @nnie., what do you think? Mail me at me@example.com</p>
+            <blockquote><p>@someone asked: why?</p></blockquote>
+            "#,
+        );
+        assert_eq!(content.mentions, ["tyrdal", "nnie"]);
+        assert!(content.question);
+        // Questions and mentions that are only quoted do not count.
+        let content = content_of("<blockquote><p>Why, @nnie?</p></blockquote><p>Agreed.</p>");
+        assert!(content.mentions.is_empty());
+        assert!(!content.question);
+        // Only a question mark that ends a sentence asks something.
+        let cases = [
+            ("Is it \"the end?\" Yes.", true),
+            ("See (what?) here", true),
+            (
+                "Read https://www.worldanvil.com/w/solaris?page=2 for more",
+                false,
+            ),
+            ("No way?! That is wild.", false),
+            ("No way?!?", true),
+        ];
+        for (text, question) in cases {
+            assert_eq!(
+                content_of(&format!("<p>{text}</p>")).question,
+                question,
+                "{text}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_extract_content_escapes_and_drops_unsafe_links() {
+        let content =
+            content_of(r#"<p onclick="x()">1 &lt; 2 <a href="javascript:alert(1)">click</a></p>"#);
+        assert_eq!(content.html, "<p>1 &lt; 2 click</p>");
+        assert_eq!(content.text, "1 < 2 click");
+    }
+}
@@ -0,0 +1,198 @@
+//! Reading the dates WorldAnvil puts on comments, in whatever language and style the page used.
+use chrono::{Offset, TimeZone};
+use chrono_tz::Tz;
+use lazy_static::lazy_static;
+use regex::Regex;
+use time::{Date, Duration, Month, OffsetDateTime, PrimitiveDateTime, Time, UtcOffset};
+
+/// When a page was fetched and the timezone it was rendered in.
+/// Relative dates such as "2 hours ago" are counted back from the fetch time.
+#[derive(Clone, Copy, Debug)]
+pub struct DateContext {
+    pub fetched_at: OffsetDateTime,
+    pub timezone: Tz,
+}
+
+impl DateContext {
+    /// The fetch time as a wall clock time in the page's timezone.
+    fn local_fetch_time(&self) -> PrimitiveDateTime {
+        let utc = chrono::DateTime::from_timestamp(self.fetched_at.unix_timestamp(), 0)
+            .unwrap_or_default()
+            .naive_utc();
+        let offset = self
+            .timezone
+            .offset_from_utc_datetime(&utc)
+            .fix()
+            .local_minus_utc();
+        let offset = UtcOffset::from_whole_seconds(offset).unwrap_or(UtcOffset::UTC);
+        let local = self.fetched_at.to_offset(offset);
+        PrimitiveDateTime::new(local.date(), local.time())
+    }
+}
+
+lazy_static! {
+    static ref RELATIVE_PATTERN: Regex =
+        Regex::new(r"^(\d+|an?|one)\s+(second|sec|minute|min|hour|hr|day|week)s?\s+ago$").unwrap();
+    static ref TIME_PATTERN: Regex = Regex::new(r"^(\d{1,2}):(\d{2})(am|pm)?$").unwrap();
+}
+
+/// Month names and abbreviations in the interface languages WorldAnvil offers.
+/// Abbreviations are matched as prefixes, so "sept", "sept." and "september" all work.
+const MONTHS: [(Month, &[&str]); 12] = [
+    (Month::January, &["jan", "ene", "gen", "sty"]),
+    (Month::February, &["feb", "fév", "fev", "lut"]),
+    (Month::March, &["mar", "mär", "mrt", "maa"]),
+    (Month::April, &["apr", "avr", "abr", "kwi"]),
+    (Month::May, &["may", "mai", "mei", "mag", "maj"]),
+    (Month::June, &["jun", "juin", "giu", "cze"]),
+    (Month::July, &["jul", "juil", "lug", "lip"]),
+    (Month::August, &["aug", "août", "aout", "ago", "sie"]),
+    (Month::September, &["sep", "set", "wrz"]),
+    (Month::October, &["oct", "okt", "ott", "out", "paź", "paz"]),
+    (Month::November, &["nov", "lis"]),
+    (Month::December, &["dec", "dez", "déc", "dic", "gru"]),
+];
+
+fn parse_month(token: &str) -> Option<Month> {
+    MONTHS
+        .iter()
+        .find(|(_, names)| names.iter().any(|name| token.starts_with(name)))
+        .map(|(month, _)| *month)
+}
+
+/// Parse "2 hours ago", "just now" and "yesterday".
+fn parse_relative(s: &str) -> Option<Duration> {
+    match s {
+        "just now" | "now" | "a moment ago" | "moments ago" => return Some(Duration::ZERO),
+        "yesterday" => return Some(Duration::days(1)),
+        _ => {}
+    }
+    let capture = RELATIVE_PATTERN.captures(s)?;
+    let amount = match &capture[1] {
+        "a" | "an" | "one" => 1,
+        n => n.parse().ok()?,
+    };
+    Some(match &capture[2] {
+        "second" | "sec" => Duration::seconds(amount),
+        "minute" | "min" => Duration::minutes(amount),
+        "hour" | "hr" => Duration::hours(amount),
+        "day" => Duration::days(amount),
+        _ => Duration::weeks(amount),
+    })
+}
+
+/// Parse a clock time, in 24-hour or 12-hour style.
+fn parse_time(token: &str, meridiem: Option<&str>) -> Option<Time> {
+    let capture = TIME_PATTERN.captures(token)?;
+    let mut hour: u8 = capture[1].parse().ok()?;
+    let minute: u8 = capture[2].parse().ok()?;
+    match capture.get(3).map(|m| m.as_str()).or(meridiem) {
+        Some("am") if hour == 12 => hour = 0,
+        Some("pm") if hour < 12 => hour += 12,
+        _ => {}
+    }
+    Time::from_hms(hour, minute, 0).ok()
+}
+
+/// The wall clock time a comment date was rendered as.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ParsedDate {
+    pub datetime: PrimitiveDateTime,
+    /// The date was relative, such as "2 hours ago". It is only as precise as its unit and
+    /// comes out differently every time the page is fetched.
+    pub approximate: bool,
+}
+
+/// Parse the date of a comment into the wall clock time it was rendered as.
+/// Handles "Aug 24, 2024 03:12" and its variations: full or localized month names,
+/// day before month, 12-hour clocks, and relative times counted back from the fetch time.
+pub fn parse_date(s: &str, context: &DateContext) -> Option<ParsedDate> {
+    let normalized = s.trim().to_lowercase();
+    let normalized = normalized.split_whitespace().collect::<Vec<_>>().join(" ");
+    if let Some(ago) = parse_relative(&normalized) {
+        return Some(ParsedDate {
+            datetime: context.local_fetch_time() - ago,
+            approximate: true,
+        });
+    }
+
+    let cleaned = normalized.replace([',', '.'], " ");
+    let tokens: Vec<_> = cleaned.split_whitespace().collect();
+    let meridiem = tokens
+        .iter()
+        .find(|token| matches!(**token, "am" | "pm"))
+        .copied();
+    let (mut month, mut day, mut year, mut time) = (None, None, None, None);
+    for token in &tokens {
+        if token.contains(':') {
+            time = parse_time(token, meridiem);
+        } else if token.chars().all(|c| c.is_ascii_digit()) {
+            match token.len() {
+                4 => year = token.parse().ok(),
+                1 | 2 => day = token.parse().ok(),
+                _ => return None,
+            }
+        } else if month.is_none() {
+            month = parse_month(token);
+        }
+    }
+    let date = Date::from_calendar_date(year?, month?, day?).ok()?;
+    Some(ParsedDate {
+        datetime: PrimitiveDateTime::new(date, time?),
+        approximate: false,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use time::macros::datetime;
+
+    #[test]
+    fn test_parse_date() {
+        let context = DateContext {
+            fetched_at: datetime!(2025-01-15 12:30 UTC),
+            timezone: Tz::America__New_York,
+        };
+        // Every format seen on WorldAnvil so far. Relative dates are counted back from
+        // 07:30 New York time.
+        let cases = [
+            ("Aug 24, 2024 03:12", Some(datetime!(2024-08-24 03:12))),
+            ("Aug 8, 2024 13:43", Some(datetime!(2024-08-08 13:43))),
+            ("  Aug 8, 2024\n 13:43 ", Some(datetime!(2024-08-08 13:43))),
+            ("August 24, 2024 03:12", Some(datetime!(2024-08-24 03:12))),
+            ("Sept. 3, 2024 18:00", Some(datetime!(2024-09-03 18:00))),
+            ("24 Aug, 2024 03:12", Some(datetime!(2024-08-24 03:12))),
+            ("Aug 24, 2024 3:12 PM", Some(datetime!(2024-08-24 15:12))),
+            ("Aug 24, 2024 12:05 am", Some(datetime!(2024-08-24 00:05))),
+            ("Aug 24, 2024 12:05pm", Some(datetime!(2024-08-24 12:05))),
+            ("24. Okt. 2024 03:12", Some(datetime!(2024-10-24 03:12))),
+            ("3 März 2024 09:00", Some(datetime!(2024-03-03 09:00))),
+            ("24 août 2024 03:12", Some(datetime!(2024-08-24 03:12))),
+            ("5 juil. 2024 21:40", Some(datetime!(2024-07-05 21:40))),
+            ("1 juin 2024 08:15", Some(datetime!(2024-06-01 08:15))),
+            ("24 dic 2024 03:12", Some(datetime!(2024-12-24 03:12))),
+            ("just now", Some(datetime!(2025-01-15 07:30))),
+            ("a minute ago", Some(datetime!(2025-01-15 07:29))),
+            ("45 minutes ago", Some(datetime!(2025-01-15 06:45))),
+            ("2 hours ago", Some(datetime!(2025-01-15 05:30))),
+            ("an hour ago", Some(datetime!(2025-01-15 06:30))),
+            ("3 days ago", Some(datetime!(2025-01-12 07:30))),
+            ("yesterday", Some(datetime!(2025-01-14 07:30))),
+            ("1 week ago", Some(datetime!(2025-01-08 07:30))),
+            ("Aug 24, 2024", None),
+            ("Smarch 24, 2024 03:12", None),
+            ("Feb 30, 2024 03:12", None),
+            ("", None),
+        ];
+        for (raw, expected) in cases {
+            let parsed = parse_date(raw, &context);
+            assert_eq!(parsed.map(|date| date.datetime), expected, "{raw}");
+            let relative = raw.ends_with("ago") || raw.ends_with("now") || raw == "yesterday";
+            assert!(
+                parsed.is_none_or(|date| date.approximate == relative),
+                "{raw}"
+            );
+        }
+    }
+}
@@ -0,0 +1,1273 @@
+use crate::dateutil::{assume_timezone, primitive_date_as_human_friendly, timezone_option_as_name};
+use crate::db::schema::{
+    ArticleMetadata, CommentInsert, CommentReplyInsert, PageKind, WorldAnvilUserInsert,
+};
+use crate::parser::content::{extract_content, CommentContent};
+use crate::parser::date::{parse_date, DateContext, ParsedDate};
+use crate::parser::profile::CompiledProfile;
+use crate::req::{page_client, page_rate_limiter};
+use anyhow;
+use chrono_tz::Tz;
+use itertools::Itertools;
+use lazy_static::lazy_static;
+use regex::Regex;
+use reqwest::header::{ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED};
+use reqwest::{StatusCode, Url};
+use scraper::{ElementRef, Html, Selector};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::collections::HashSet;
+use time::macros::format_description;
+use time::{OffsetDateTime, PrimitiveDateTime};
+
+pub mod content;
+pub mod date;
+pub mod profile;
+pub mod snapshot;
+pub mod state;
+
+#[derive(Serialize)]
+pub struct Article {
+    pub title: String,
+    /// Only articles and the world homepage carry their id in the page.
+    pub worldanvil_id: Option<String>,
+    pub world_worldanvil_id: String,
+    pub metadata: ArticleMetadata,
+    pub comments: Vec<RootComment>,
+    /// The timezone comment dates were rendered in, if the page says so.
+    #[serde(serialize_with = "timezone_option_as_name")]
+    pub timezone: Option<Tz>,
+    /// Problems with individual comments which were skipped while parsing.
+    pub warnings: Vec<String>,
+    /// Link to the next page of comments, as written on the page.
+    pub next_page: Option<String>,
+    /// The label of the selector profile that parsed the page.
+    pub profile: String,
+    /// Where that profile is in the list the page was parsed with.
+    #[serde(skip)]
+    pub profile_index: usize,
+}
+
+impl Article {
+    /// Every comment and reply in the article, in page order.
+    pub fn all_comments(&self) -> impl Iterator<Item = &Comment> {
+        self.comments
+            .iter()
+            .flat_map(|root| std::iter::once(&root.comment).chain(&root.replies))
+    }
+
+    /// Append a further page of comments to the article.
+    /// Threads that shifted onto the next page while it was being fetched are only kept once.
+    /// Those with an anchor are recognised even if they were edited in between, the others only
+    /// if their content is the same, as that is what tells them apart from another comment by
+    /// the same author in the same minute. Comments sharing an identity are then told apart
+    /// across all pages.
+    pub fn merge(&mut self, page: CommentsPage) {
+        let seen: HashSet<_> = self
+            .comments
+            .iter()
+            .map(|thread| thread.comment.shift_key())
+            .collect();
+        let comments: Vec<_> = page
+            .comments
+            .into_iter()
+            .filter(|thread| !seen.contains(&thread.comment.shift_key()))
+            .collect();
+        self.comments.extend(comments);
+        disambiguate_identities(&mut self.comments);
+        self.warnings.extend(page.warnings);
+        self.next_page = page.next_page;
+    }
+}
+
+/// The comments found on a further page of an article's comment section.
+#[derive(Debug, Serialize)]
+pub struct CommentsPage {
+    pub comments: Vec<RootComment>,
+    pub warnings: Vec<String>,
+    pub next_page: Option<String>,
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum ParseError {
+    #[error("could not find #visual-container, is the article private?")]
+    NoVisualContainer,
+    #[error("could not find #content .article-title h1, is the header hidden?")]
+    NoHeader,
+    #[error("could not find .page-article-main, is there no article content?")]
+    NoPageArticleMain,
+    #[error("could not find the world-<id> class on #visual-container")]
+    MissingWorldClass,
+    #[error("could not find the article-<id> class on .page-article-main")]
+    MissingArticleClass,
+    #[error("could not find the author of comment {index}")]
+    MissingAuthor { index: i16 },
+    #[error("could not find the date of comment {index}")]
+    MissingDate { index: i16 },
+    #[error("could not parse date '{raw}' of comment {index}")]
+    BadDate { index: i16, raw: String },
+    #[error("could not find the content of comment {index}")]
+    MissingContent { index: i16 },
+    #[error("the page shows comments but none could be read, do the comment selectors match?")]
+    CommentsNotFound,
+    #[error("there are no selector profiles to parse the page with")]
+    NoProfiles,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RootComment {
+    pub comment: Comment,
+    pub replies: Vec<Comment>,
+}
+
+impl RootComment {
+    pub fn datetime(&self) -> &PrimitiveDateTime {
+        &self.comment.comment_datetime
+    }
+
+    pub fn content(&self) -> &str {
+        &self.comment.content
+    }
+
+    pub fn author_worldanvil_id(&self) -> Option<&str> {
+        self.comment.author_worldanvil_id.as_deref()
+    }
+
+    /// Whether the world owner has answered this thread.
+    /// The owner's own comments never need an answer. Otherwise the owner has to have replied,
+    /// and the commenter must not have followed up after the owner's last reply.
+    pub fn is_answered(&self, owner_worldanvil_id: &str) -> bool {
+        let is_owner = |comment: &Comment| {
+            comment.author_worldanvil_id.as_deref() == Some(owner_worldanvil_id)
+        };
+        if is_owner(&self.comment) {
+            return true;
+        }
+        let last_owner_reply = match self.replies.iter().rposition(is_owner) {
+            Some(i) => i,
+            None => return false,
+        };
+        let commenter = self.author_worldanvil_id();
+        !self.replies[last_owner_reply + 1..]
+            .iter()
+            .any(|reply| commenter.is_some() && reply.author_worldanvil_id.as_deref() == commenter)
+    }
+
+    pub fn as_db_comment(
+        &self,
+        user_id: i64,
+        author_id: Option<i64>,
+        article_id: i64,
+        owner_worldanvil_id: &str,
+        timezone: Tz,
+    ) -> CommentInsert {
+        CommentInsert {
+            user_id,
+            author_id,
+            article_id,
+            identity: self.comment.identity.clone(),
+            content: self.comment.content.clone(),
+            content_html: self.comment.content_html.clone(),
+            mentions: self.comment.mentions.clone(),
+            links: self.comment.links.clone(),
+            question: self.comment.question,
+            date: assume_timezone(self.comment.comment_datetime, timezone),
+            approximate_date: self.comment.approximate_date,
+            answered: self.is_answered(owner_worldanvil_id),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct Comment {
+    pub index: i16,
+    /// Stays the same across checks so the stored comment can be updated in place.
+    /// This is WorldAnvil's anchor for the comment, or else the author and the date.
+    /// Comments with a relative date are identified by their author and content instead.
+    pub identity: String,
+    /// The identity before comments sharing it were told apart.
+    #[serde(skip)]
+    pub base_identity: String,
+    /// The identity the comment had while its date was relative, for comments without an anchor.
+    /// Lets a comment first seen as "2 hours ago" be found again once it shows its full date.
+    #[serde(skip)]
+    pub approximate_identity: Option<String>,
+    /// None if the comment has no comment-author-<id> class, e.g. for deleted accounts.
+    pub author_worldanvil_id: Option<String>,
+    pub author_avatar: Option<String>,
+    pub author_name: String,
+    #[serde(serialize_with = "primitive_date_as_human_friendly")]
+    pub comment_datetime: PrimitiveDateTime,
+    /// The date was relative, so comment_datetime is a guess that changes with every fetch.
+    #[serde(skip)]
+    pub approximate_date: bool,
+    /// The comment body as normalized plain text.
+    pub content: String,
+    /// The comment body as sanitized html, keeping links, mentions and formatting.
+    pub content_html: String,
+    /// Lowercased names of the users mentioned in the comment.
+    pub mentions: Vec<String>,
+    pub links: Vec<String>,
+    /// The comment asks a question.
+    pub question: bool,
+}
+
+impl Comment {
+    /// What recognises the same thread on two pages of comments.
+    fn shift_key(&self) -> (String, Option<String>) {
+        let content = self
+            .approximate_identity
+            .is_some()
+            .then(|| self.content.clone());
+        (self.base_identity.clone(), content)
+    }
+
+    /// Only comments with a known author can be turned into a worldanvil user.
+    pub fn as_worldanvil_user(&self) -> Option<WorldAnvilUserInsert> {
+        self.author_worldanvil_id
+            .as_ref()
+            .map(|worldanvil_id| WorldAnvilUserInsert {
+                worldanvil_id: Some(worldanvil_id.clone()),
+                name: self.author_name.clone(),
+                avatar_url: self.author_avatar.clone(),
+            })
+    }
+
+    pub fn as_db_reply(
+        &self,
+        user_id: i64,
+        author_id: Option<i64>,
+        article_id: i64,
+        timezone: Tz,
+    ) -> CommentReplyInsert {
+        CommentReplyInsert {
+            user_id,
+            author_id,
+            article_id,
+            identity: self.identity.clone(),
+            content: self.content.clone(),
+            content_html: self.content_html.clone(),
+            mentions: self.mentions.clone(),
+            links: self.links.clone(),
+            question: self.question,
+            date: assume_timezone(self.comment_datetime, timezone),
+            approximate_date: self.approximate_date,
+        }
+    }
+}
+
+/// What identified a page when it was last fetched. Sent back to only download it if it changed.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct PageValidators {
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+}
+
+/// A downloaded page, after following any redirects.
+pub struct FetchedPage {
+    pub status: StatusCode,
+    /// Where the page was finally fetched from.
+    pub url: Url,
+    pub fetched_at: OffsetDateTime,
+    pub body: String,
+    pub validators: PageValidators,
+}
+
+pub async fn get_page(url: &str) -> anyhow::Result<FetchedPage> {
+    get_page_if_changed(url, &PageValidators::default()).await
+}
+
+/// Fetch a page unless it is the same as when it had `validators`,
+/// in which case the status is 304 Not Modified and the body is empty.
+pub async fn get_page_if_changed(
+    url: &str,
+    validators: &PageValidators,
+) -> anyhow::Result<FetchedPage> {
+    page_rate_limiter().acquire().await;
+    let mut request = page_client().get(url);
+    if let Some(etag) = &validators.etag {
+        request = request.header(IF_NONE_MATCH, etag);
+    }
+    if let Some(last_modified) = &validators.last_modified {
+        request = request.header(IF_MODIFIED_SINCE, last_modified);
+    }
+    let r = request.send().await?;
+    let header = |name| {
+        r.headers()
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_string)
+    };
+    let validators = PageValidators {
+        etag: header(ETAG),
+        last_modified: header(LAST_MODIFIED),
+    };
+    Ok(FetchedPage {
+        status: r.status(),
+        url: r.url().clone(),
+        fetched_at: OffsetDateTime::now_utc(),
+        body: r.text().await?,
+        validators,
+    })
+}
+
+lazy_static! {
+    static ref TIMEZONE_ATTRIBUTE_SELECTOR: Selector = Selector::parse("[data-timezone]").unwrap();
+    static ref TIMEZONE_META_SELECTOR: Selector =
+        Selector::parse(r#"meta[name="timezone"]"#).unwrap();
+    static ref SCRIPT_SELECTOR: Selector = Selector::parse("script").unwrap();
+    static ref WORLDANVIL_ID_PATTERN: Regex =
+        Regex::new(r#"^([\w-]+)-(\w{8}-\w{4}-\w{4}-\w{4}-\w{12})$"#).unwrap();
+    static ref TIMEZONE_SCRIPT_PATTERN: Regex =
+        Regex::new(r#"(?i)timezone["']?\s*[:=]\s*["']([A-Za-z_]+(?:/[\w+-]+)+)["']"#).unwrap();
+}
+
+/// Get the text contents of the node while trimming extra whitespace.
+fn get_text_content(element: &ElementRef) -> String {
+    Itertools::intersperse(element.text().map(str::trim), "\n").collect()
+}
+
+/// Find the class of the element that matches the pattern.
+/// Return the element's 1-th group, or None if no class has the prefix.
+fn find_class_with_prefix(element: &ElementRef, prefix: &str) -> Option<String> {
+    let classes = element.attr("class")?;
+    classes
+        .split_whitespace()
+        .filter_map(|class| WORLDANVIL_ID_PATTERN.captures(class))
+        .find(|capture| &capture[1] == prefix)
+        .map(|capture| capture[2].to_string())
+}
+
+/// Find the timezone the page was rendered in, if the page tells us.
+/// Checks data-timezone attributes, a timezone meta tag, then timezone settings in inline scripts.
+fn find_page_timezone(page: &Html) -> Option<Tz> {
+    let from_attributes = page
+        .select(&TIMEZONE_ATTRIBUTE_SELECTOR)
+        .filter_map(|node| node.attr("data-timezone"))
+        .chain(
+            page.select(&TIMEZONE_META_SELECTOR)
+                .filter_map(|node| node.attr("content")),
+        )
+        .map(str::to_string);
+    let from_scripts = page
+        .select(&SCRIPT_SELECTOR)
+        .flat_map(|script| script.text())
+        .filter_map(|text| TIMEZONE_SCRIPT_PATTERN.captures(text))
+        .map(|capture| capture[1].to_string());
+    from_attributes
+        .chain(from_scripts)
+        .find_map(|tz| tz.trim().parse().ok())
+}
+
+/// The anchor WorldAnvil gives a comment, so it can be linked to.
+fn find_comment_anchor(element: &ElementRef) -> Option<String> {
+    element
+        .attr("data-comment-id")
+        .or_else(|| element.attr("id"))
+        .map(str::trim)
+        .filter(|anchor| !anchor.is_empty())
+        .map(str::to_string)
+}
+
+/// Identify a comment by its anchor, falling back to its author and the minute it was posted.
+/// Relative dates shift with every fetch, so comments with one are identified by their author
+/// and content instead. Also returns that content based identity for comments without an anchor.
+fn comment_identity(
+    element: &ElementRef,
+    author_worldanvil_id: Option<&str>,
+    author_name: &str,
+    date: &ParsedDate,
+    content: &str,
+) -> (String, Option<String>) {
+    if let Some(anchor) = find_comment_anchor(element) {
+        return (anchor, None);
+    }
+    let author = author_worldanvil_id
+        .map(str::to_string)
+        .unwrap_or_else(|| format!("name:{author_name}"));
+    let approximate = format!("{author}@~{}", content_digest(content));
+    if date.approximate {
+        return (approximate.clone(), Some(approximate));
+    }
+    let minute = date
+        .datetime
+        .format(format_description!("[year]-[month]-[day]T[hour]:[minute]"))
+        .expect("the format only uses fields a PrimitiveDateTime has");
+    (format!("{author}@{minute}"), Some(approximate))
+}
+
+/// A short fingerprint of a comment's text.
+/// It is stored as part of identities, so it must not change between releases.
+/// migrations/013_comment_identity.sql computes the same fingerprint in SQL.
+pub fn content_digest(content: &str) -> String {
+    Sha256::digest(content.as_bytes())[..4]
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect()
+}
+
+/// Extract common comment info from a node
+fn get_comment_info(
+    element: &ElementRef,
+    index: i16,
+    profile: &CompiledProfile,
+    dates: &DateContext,
+) -> Result<Comment, ParseError> {
+    let author_name = select_own(element, &profile.author_name, profile)
+        .next()
+        .map(|node| get_text_content(&node))
+        .ok_or(ParseError::MissingAuthor { index })?;
+    // Users without an avatar have no img at all.
+    let author_avatar = select_own(element, &profile.author_avatar, profile)
+        .next()
+        .and_then(|img| img.attr("src"))
+        .map(str::to_string);
+    let datetime_str = select_own(element, &profile.date, profile)
+        .next()
+        .and_then(|node| node.text().map(str::trim).find(|text| !text.is_empty()))
+        .ok_or(ParseError::MissingDate { index })?;
+    let date = parse_date(datetime_str, dates).ok_or_else(|| ParseError::BadDate {
+        index,
+        raw: datetime_str.to_string(),
+    })?;
+    let CommentContent {
+        html: content_html,
+        text: content,
+        mentions,
+        links,
+        question,
+    } = select_own(element, &profile.content, profile)
+        .next()
+        .map(|node| extract_content(&node))
+        .ok_or(ParseError::MissingContent { index })?;
+    let author_worldanvil_id = find_class_with_prefix(element, &profile.author_class_prefix);
+    let (identity, approximate_identity) = comment_identity(
+        element,
+        author_worldanvil_id.as_deref(),
+        &author_name,
+        &date,
+        &content,
+    );
+    Ok(Comment {
+        index,
+        base_identity: identity.clone(),
+        identity,
+        approximate_identity,
+        author_worldanvil_id,
+        author_avatar,
+        author_name,
+        comment_datetime: date.datetime,
+        approximate_date: date.approximate,
+        content,
+        content_html,
+        mentions,
+        links,
+        question,
+    })
+}
+
+/// Select the descendants of a comment that belong to it rather than to one of its replies.
+fn select_own<'a>(
+    element: &ElementRef<'a>,
+    selector: &'a Selector,
+    profile: &'a CompiledProfile,
+) -> impl Iterator<Item = ElementRef<'a>> + 'a {
+    let root = element.id();
+    element.select(selector).filter(move |node| {
+        !node
+            .ancestors()
+            .take_while(|ancestor| ancestor.id() != root)
+            .filter_map(ElementRef::wrap)
+            .any(|ancestor| profile.reply.matches(&ancestor))
+    })
+}
+
+/// Parse an article page and extract all information we need from it.
+/// The profiles are tried in order and the first one that can read the page is used.
+/// If none can, the error of the first profile is returned.
+/// Relative dates are read in the page's own timezone, or else the one in `dates`.
+pub fn parse_page(
+    page_body: &str,
+    kind: PageKind,
+    profiles: &[CompiledProfile],
+    dates: &DateContext,
+) -> Result<Article, ParseError> {
+    parse_html(&Html::parse_document(page_body), kind, profiles, dates)
+}
+
+fn parse_html(
+    page: &Html,
+    kind: PageKind,
+    profiles: &[CompiledProfile],
+    dates: &DateContext,
+) -> Result<Article, ParseError> {
+    let mut first_error = None;
+    for (index, profile) in profiles.iter().enumerate() {
+        match parse_page_with_profile(page, kind, profile, dates) {
+            Ok(article) => {
+                return Ok(Article {
+                    profile_index: index,
+                    ..article
+                })
+            }
+            Err(e) => {
+                log::debug!("Selector profile {} failed: {e}", profile.label());
+                first_error.get_or_insert(e);
+            }
+        }
+    }
+    Err(first_error.unwrap_or(ParseError::NoProfiles))
+}
+
+fn parse_page_with_profile(
+    page: &Html,
+    kind: PageKind,
+    profile: &CompiledProfile,
+    dates: &DateContext,
+) -> Result<Article, ParseError> {
+    let world_node = page
+        .select(&profile.visual_container)
+        .next()
+        .ok_or(ParseError::NoVisualContainer)?;
+    let world_worldanvil_id = find_class_with_prefix(&world_node, &profile.world_class_prefix)
+        .ok_or(ParseError::MissingWorldClass)?;
+    let title_selector = match kind {
+        PageKind::Article => &profile.title,
+        _ => &profile.page_title,
+    };
+    let title_node = page
+        .select(title_selector)
+        .next()
+        .ok_or(ParseError::NoHeader)?;
+    // Find all the text nodes, then join and split
+    let title = title_node.text().collect::<String>().trim().to_string();
+    let (worldanvil_id, metadata) = match kind {
+        PageKind::Article => {
+            let article_node = page
+                .select(&profile.article_main)
+                .next()
+                .ok_or(ParseError::NoPageArticleMain)?;
+            let worldanvil_id =
+                find_class_with_prefix(&article_node, &profile.article_class_prefix)
+                    .ok_or(ParseError::MissingArticleClass)?;
+            (
+                Some(worldanvil_id),
+                find_metadata(page, Some(&article_node), profile),
+            )
+        }
+        PageKind::World => (
+            Some(world_worldanvil_id.clone()),
+            find_metadata(page, None, profile),
+        ),
+        _ => (None, find_metadata(page, None, profile)),
+    };
+    let timezone = find_page_timezone(page);
+    let CommentsPage {
+        comments,
+        warnings,
+        next_page,
+    } = parse_comments(
+        page,
+        profile,
+        &DateContext {
+            timezone: timezone.unwrap_or(dates.timezone),
+            ..*dates
+        },
+    );
+    // A profile whose comment selectors match nothing would read every page as uncommented.
+    if comments.is_empty() && !metadata.comments_disabled && page_shows_comments(page, profile) {
+        return Err(ParseError::CommentsNotFound);
+    }
+
+    Ok(Article {
+        title,
+        worldanvil_id,
+        world_worldanvil_id,
+        metadata,
+        comments,
+        timezone,
+        warnings,
+        next_page,
+        profile: profile.label(),
+        profile_index: 0,
+    })
+}
+
+/// Whether the page has comments, judged without the comment selectors: by a comment count in
+/// the metadata table, or by comment authors or anchors in the comment section.
+fn page_shows_comments(page: &Html, profile: &CompiledProfile) -> bool {
+    let counted = page.select(&profile.metadata_label).any(|label| {
+        label
+            .text()
+            .collect::<String>()
+            .trim()
+            .eq_ignore_ascii_case("comments")
+            && label
+                .next_siblings()
+                .find_map(ElementRef::wrap)
+                .and_then(|value| parse_count(&value.text().collect::<String>()))
+                .is_some_and(|count| count > 0)
+    });
+    let author_class = format!("{}-", profile.author_class_prefix);
+    counted
+        || page.select(&profile.comment_section).any(|section| {
+            section
+                .descendants()
+                .filter_map(ElementRef::wrap)
+                .any(|node| {
+                    node.attr("data-comment-id").is_some()
+                        || node
+                            .value()
+                            .classes()
+                            .any(|class| class.starts_with(&author_class))
+                })
+        })
+}
+
+/// Read the article metadata table and whether the comment section is closed.
+fn find_metadata(
+    page: &Html,
+    article_node: Option<&ElementRef>,
+    profile: &CompiledProfile,
+) -> ArticleMetadata {
+    let mut metadata = ArticleMetadata::default();
+    for label in page.select(&profile.metadata_label) {
+        let Some(value) = label.next_siblings().find_map(ElementRef::wrap) else {
+            continue;
+        };
+        let text = value.text().collect::<String>().trim().to_string();
+        match label
+            .text()
+            .collect::<String>()
+            .trim()
+            .to_lowercase()
+            .as_str()
+        {
+            "author" => metadata.author = Some(text),
+            "article template" | "article type" => metadata.article_type = Some(text),
+            "views" => metadata.views = parse_count(&text),
+            "favorites" | "likes" | "hearts" => metadata.likes = parse_count(&text),
+            "words" | "word count" => metadata.word_count = parse_count(&text),
+            "tags" => {
+                metadata.tags = value
+                    .select(&profile.tag)
+                    .filter_map(|tag| tag.attr("data-tag"))
+                    .map(str::to_string)
+                    .collect()
+            }
+            _ => {}
+        }
+    }
+    // Articles without the metadata table still carry their template as a class.
+    if let (None, Some(article_node)) = (&metadata.article_type, article_node) {
+        metadata.article_type = article_node
+            .value()
+            .classes()
+            .find_map(|class| class.strip_prefix("template-"))
+            .map(|template| {
+                let mut chars = template.chars();
+                chars
+                    .next()
+                    .map(|first| first.to_uppercase().chain(chars).collect())
+                    .unwrap_or_default()
+            });
+    }
+    metadata.comments_disabled = page.select(&profile.comments_disabled).next().is_some()
+        || page.select(&profile.comment_section).any(|section| {
+            let text = section.text().collect::<String>().to_lowercase();
+            text.contains("comments are disabled") || text.contains("comments have been disabled")
+        });
+    metadata
+}
+
+/// Parse a count such as "1,234" or "1.2k".
+fn parse_count(text: &str) -> Option<i32> {
+    let text: String = text
+        .chars()
+        .filter(|c| !c.is_whitespace() && *c != ',')
+        .collect::<String>()
+        .to_lowercase();
+    match text.strip_suffix('k') {
+        Some(thousands) => thousands
+            .parse::<f64>()
+            .ok()
+            .map(|n| (n * 1000.0).round() as i32),
+        None => text.parse().ok(),
+    }
+}
+
+/// Parse a further page of comments, either a full article page or a lazily loaded fragment.
+/// It should be read with the profile that parsed the article itself.
+pub fn parse_comments_page(
+    page_body: &str,
+    profile: &CompiledProfile,
+    dates: &DateContext,
+) -> CommentsPage {
+    parse_comments(&Html::parse_document(page_body), profile, dates)
+}
+
+/// Handle all comments and their replies.
+/// A broken comment or reply is skipped with a warning instead of failing the whole page.
+fn parse_comments(page: &Html, profile: &CompiledProfile, dates: &DateContext) -> CommentsPage {
+    let mut comments = vec![];
+    let mut warnings = vec![];
+    for (index, element) in page.select(&profile.comment).enumerate() {
+        let comment = match get_comment_info(&element, index as i16, profile, dates) {
+            Ok(comment) => comment,
+            Err(e) => {
+                warnings.push(e.to_string());
+                continue;
+            }
+        };
+        let mut replies = vec![];
+        for (reply_index, reply) in element.select(&profile.reply).enumerate() {
+            match get_comment_info(&reply, reply_index as i16, profile, dates) {
+                Ok(reply) => replies.push(reply),
+                Err(e) => warnings.push(format!("reply to comment {index}: {e}")),
+            }
+        }
+        comments.push(RootComment { comment, replies });
+    }
+    disambiguate_identities(&mut comments);
+    CommentsPage {
+        comments,
+        warnings,
+        next_page: find_next_comments_page(page, profile),
+    }
+}
+
+/// The same author can post twice in the same minute, so comments sharing an identity are told
+/// apart by their content. Only comments that are identical in every way are numbered in page
+/// order, as nothing else distinguishes them.
+/// Starts from the base identities, so it can run again once more comments are added.
+fn disambiguate_identities(comments: &mut [RootComment]) {
+    let mut all: Vec<_> = comments
+        .iter_mut()
+        .flat_map(|root| std::iter::once(&mut root.comment).chain(&mut root.replies))
+        .collect();
+    let counts = all
+        .iter()
+        .counts_by(|comment| comment.base_identity.clone());
+    let mut seen = HashSet::new();
+    for comment in all.iter_mut() {
+        let mut base = comment.base_identity.clone();
+        if counts[&base] > 1 {
+            base = format!("{base}#{}", content_digest(&comment.content));
+        }
+        let mut identity = base.clone();
+        let mut count = 1;
+        while seen.contains(&identity) {
+            count += 1;
+            identity = format!("{base}-{count}");
+        }
+        seen.insert(identity.clone());
+        comment.identity = identity;
+    }
+}
+
+/// Find the "more comments" button or the next link of the comment pagination.
+fn find_next_comments_page(page: &Html, profile: &CompiledProfile) -> Option<String> {
+    page.select(&profile.next_comments_page)
+        .filter_map(|element| {
+            let value = element.value();
+            value.attr("data-comments-next").or(value.attr("href"))
+        })
+        .map(str::trim)
+        .find(|link| !link.is_empty() && !link.starts_with('#'))
+        .map(str::to_string)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::parser::profile::{default_profile, SelectorProfile};
+    use crate::parser::snapshot::SNAPSHOT_DATES;
+    use std::fs;
+    use time::macros::datetime;
+
+    /// Parse a page with the built-in selector profile.
+    fn parse(page: &str) -> Result<Article, ParseError> {
+        parse_page(
+            page,
+            PageKind::Article,
+            std::slice::from_ref(default_profile()),
+            &SNAPSHOT_DATES,
+        )
+    }
+
+    #[test]
+    fn test_parse_page() {
+        let fixture = fs::read_to_string("fixtures/example-solaris-page.htm").unwrap();
+        let article = parse(&fixture).unwrap();
+        assert_eq!(article.title, "Chewpaper");
+        assert_eq!(
+            article.world_worldanvil_id,
+            "e69d6a36-2d22-4bf2-80f9-456a9b0d909e"
+        );
+        assert_eq!(
+            article.worldanvil_id.as_deref(),
+            Some("4cdfec2c-b875-4dc6-b5c9-146470e9ac80")
+        );
+        assert_eq!(article.metadata.author.as_deref(), Some("nnie"));
+        assert_eq!(article.metadata.article_type.as_deref(), Some("Material"));
+        assert_eq!(article.metadata.views, Some(97));
+        assert_eq!(article.metadata.likes, Some(17));
+        assert_eq!(article.metadata.word_count, None);
+        assert_eq!(
+            article.metadata.tags[..2],
+            ["index-c-material", "mercury-material"]
+        );
+        assert!(!article.metadata.comments_disabled);
+        let expected_authors = [
+            ("Tyrdal", "d05d748e-57d9-42f6-80fc-eff50fabda50"),
+            ("CoolG1319", "b51561d7-f49f-4493-85b1-5f5b2ff4c243"),
+            ("skairunner", "9fe45c42-cb7e-47f0-bfb0-bd98762dda16"),
+        ];
+        for ((expected_author, expected_id), comment) in
+            expected_authors.iter().zip(&article.comments)
+        {
+            assert_eq!(*expected_author, comment.comment.author_name);
+            assert_eq!(Some(*expected_id), comment.author_worldanvil_id());
+            assert_eq!(comment.replies.len(), 1);
+            assert_eq!(comment.replies[0].author_name, "nnie");
+            assert_eq!(
+                comment.replies[0].author_worldanvil_id.as_deref(),
+                Some("225bd01d-124c-4aa2-885b-0fc4bdf41bd8")
+            );
+            assert!(comment.comment.identity.starts_with(expected_id));
+        }
+        assert_eq!(
+            article.comments[0].content(),
+            "This is a lovely idea and a great trip back memory lane for me. \
+            Thanks for this wonderful little article."
+        );
+        assert_eq!(
+            article.comments[0].replies[0].content_html,
+            "<p>Thank you!</p>"
+        );
+        assert_eq!(
+            article.comments[0].comment.identity,
+            "d05d748e-57d9-42f6-80fc-eff50fabda50@2024-08-08T13:43"
+        );
+        assert_eq!(
+            article.comments[0].replies[0].identity,
+            "225bd01d-124c-4aa2-885b-0fc4bdf41bd8@2024-08-09T07:49"
+        );
+        assert!(article.warnings.is_empty());
+        println!("{:#?}", article.comments);
+    }
+
+    #[test]
+    fn test_parse_page_timezone() {
+        let fixture = fs::read_to_string("fixtures/example-solaris-page.htm").unwrap();
+        assert_eq!(parse(&fixture).unwrap().timezone, None);
+
+        let fixture = fs::read_to_string("fixtures/timezone-new-york-page.htm").unwrap();
+        let article = parse(&fixture).unwrap();
+        let timezone = article.timezone.unwrap();
+        assert_eq!(timezone, Tz::America__New_York);
+        let thread = &article.comments[0];
+        // EDT in summer, EST in winter
+        let comment = thread.as_db_comment(1, None, 1, "owner", timezone);
+        assert_eq!(comment.date, datetime!(2024-08-08 17:43 UTC));
+        let reply = thread.replies[0].as_db_reply(1, None, 1, timezone);
+        assert_eq!(reply.date, datetime!(2025-01-03 14:05 UTC));
+    }
+
+    #[test]
+    fn test_parse_page_timezone_attribute() {
+        let page = minimal_page("").replace(
+            r#"<div id="content">"#,
+            r#"<div id="content" data-timezone="Europe/Berlin">"#,
+        );
+        assert_eq!(parse(&page).unwrap().timezone, Some(Tz::Europe__Berlin));
+    }
+
+    /// Wrap comment markup in the bare minimum of an article page.
+    fn minimal_page(comments: &str) -> String {
+        format!(
+            r#"<html><body>
+            <div id="visual-container" class="world-e69d6a36-2d22-4bf2-80f9-456a9b0d909e">
+            <div id="content"><div class="article-title"><h1>Title</h1></div></div>
+            <div class="page-article-main article-4cdfec2c-b875-4dc6-b5c9-146470e9ac80"></div>
+            {comments}
+            </div></body></html>"#
+        )
+    }
+
+    #[test]
+    fn test_parse_page_without_author_or_avatar() {
+        let page = minimal_page(
+            r#"<div class="comment-box">
+                <div class="comment-box-author"><span class="uss-css-user-username">ghost</span></div>
+                <div class="comment-box-date">Aug 8, 2024 13:43</div>
+                <div class="comment-box-content"><p>Hello</p></div>
+            </div>"#,
+        );
+        let article = parse(&page).unwrap();
+        assert_eq!(article.comments.len(), 1);
+        let comment = &article.comments[0];
+        assert_eq!(comment.author_worldanvil_id(), None);
+        assert_eq!(comment.comment.author_avatar, None);
+        assert!(comment.comment.as_worldanvil_user().is_none());
+        assert!(article.warnings.is_empty());
+    }
+
+    #[test]
+    fn test_parse_page_skips_broken_comments() {
+        let page = minimal_page(
+            r#"<div class="comment-box comment-author-d05d748e-57d9-42f6-80fc-eff50fabda50">
+                <div class="comment-box-date">Aug 8, 2024 13:43</div>
+                <div class="comment-box-content"><p>No author</p></div>
+            </div>
+            <div class="comment-box comment-author-b51561d7-f49f-4493-85b1-5f5b2ff4c243">
+                <div class="comment-box-author"><span class="uss-css-user-username">CoolG1319</span></div>
+                <div class="comment-box-date">Yesterday-ish</div>
+                <div class="comment-box-content"><p>Bad date</p></div>
+            </div>
+            <div class="comment-box comment-author-9fe45c42-cb7e-47f0-bfb0-bd98762dda16">
+                <div class="comment-box-author"><span class="uss-css-user-username">skairunner</span></div>
+                <div class="comment-box-date">Aug 8, 2024 13:45</div>
+                <div class="comment-box-content"><p>Fine</p></div>
+                <div class="comment-box-reply">
+                    <div class="comment-box-author"><span class="uss-css-user-username">nnie</span></div>
+                    <div class="comment-box-content"><p>No date</p></div>
+                </div>
+            </div>"#,
+        );
+        let article = parse(&page).unwrap();
+        assert_eq!(article.comments.len(), 1);
+        assert_eq!(article.comments[0].comment.author_name, "skairunner");
+        assert!(article.comments[0].replies.is_empty());
+        assert_eq!(
+            article.warnings,
+            vec![
+                "could not find the author of comment 0",
+                "could not parse date 'Yesterday-ish' of comment 1",
+                "reply to comment 2: could not find the date of comment 0",
+            ]
+        );
+    }
+
+    /// A reply rendered above the root comment's own content must not be mistaken for it.
+    #[test]
+    fn test_parse_reply_before_content() {
+        let fixture = fs::read_to_string("fixtures/reply-before-content-page.htm").unwrap();
+        let article = parse(&fixture).unwrap();
+        let thread = &article.comments[0];
+        assert_eq!(thread.comment.author_name, "CoolG1319");
+        assert_eq!(thread.comment.comment_datetime, datetime!(2025-03-03 09:15));
+        assert_eq!(
+            thread.comment.content,
+            "Do the clocks drift when the orbit changes?"
+        );
+        assert_eq!(
+            thread.comment.author_avatar.as_deref(),
+            Some("/uploads/images/coolg-avatar.png")
+        );
+        assert_eq!(thread.replies.len(), 1);
+        assert_eq!(thread.replies[0].author_name, "nnie");
+    }
+
+    fn comment(index: i16, author: &str) -> Comment {
+        Comment {
+            index,
+            identity: format!("{author}@{index}"),
+            base_identity: format!("{author}@{index}"),
+            approximate_identity: None,
+            author_worldanvil_id: Some(author.to_string()),
+            author_avatar: None,
+            author_name: author.to_string(),
+            comment_datetime: datetime!(2024-08-24 03:12),
+            approximate_date: false,
+            content: String::new(),
+            content_html: String::new(),
+            mentions: vec![],
+            links: vec![],
+            question: false,
+        }
+    }
+
+    fn thread(author: &str, replies: &[&str]) -> RootComment {
+        RootComment {
+            comment: comment(0, author),
+            replies: replies
+                .iter()
+                .enumerate()
+                .map(|(i, author)| comment(i as i16, author))
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn test_is_answered() {
+        let owner = "owner";
+        // (root author, reply authors, answered)
+        let cases = [
+            ("reader", vec![], false),
+            ("reader", vec!["owner"], true),
+            ("reader", vec!["other"], false),
+            ("reader", vec!["other", "owner"], true),
+            ("reader", vec!["owner", "reader"], false),
+            ("reader", vec!["owner", "reader", "owner"], true),
+            ("reader", vec!["owner", "other"], true),
+            ("owner", vec![], true),
+            ("owner", vec!["reader"], true),
+        ];
+        for (author, replies, expected) in cases {
+            assert_eq!(
+                thread(author, &replies).is_answered(owner),
+                expected,
+                "{author} with replies {replies:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_comment_identity() {
+        let box_with = |class: &str, attributes: &str, author: &str, content: &str| {
+            format!(
+                r#"<div class="comment-box {class}" {attributes}>
+                <div class="comment-box-author"><span class="uss-css-user-username">{author}</span></div>
+                <div class="comment-box-date">Aug 8, 2024 13:43</div>
+                <div class="comment-box-content"><p>{content}</p></div>
+            </div>"#
+            )
+        };
+        let page = minimal_page(
+            &[
+                box_with("", r#"id="comment-1234""#, "anchored", "Hello"),
+                box_with(
+                    "comment-author-d05d748e-57d9-42f6-80fc-eff50fabda50",
+                    "",
+                    "Tyrdal",
+                    "Hello",
+                ),
+                box_with("", "", "ghost", "Hello"),
+                box_with("", "", "ghost", "Hello again"),
+                box_with("", "", "ghost", "Hello"),
+            ]
+            .join("\n"),
+        );
+        let article = parse(&page).unwrap();
+        let identities: Vec<_> = article
+            .comments
+            .iter()
+            .map(|c| c.comment.identity.as_str())
+            .collect();
+        assert_eq!(
+            identities,
+            vec![
+                "comment-1234",
+                "d05d748e-57d9-42f6-80fc-eff50fabda50@2024-08-08T13:43",
+                "name:ghost@2024-08-08T13:43#185f8db3",
+                "name:ghost@2024-08-08T13:43#c45705cb",
+                "name:ghost@2024-08-08T13:43#185f8db3-2",
+            ]
+        );
+        // The suffixes do not depend on where the comments are on the page.
+        let page = minimal_page(
+            &[
+                box_with("", "", "ghost", "Hello again"),
+                box_with("", "", "ghost", "Hello"),
+            ]
+            .join("\n"),
+        );
+        let article = parse(&page).unwrap();
+        assert_eq!(
+            article.comments[0].comment.identity,
+            "name:ghost@2024-08-08T13:43#c45705cb"
+        );
+    }
+
+    /// Relative dates move with the fetch time, so they must not change a comment's identity.
+    #[test]
+    fn test_comment_identity_relative_date() {
+        let page = minimal_page(
+            r#"<div class="comment-box">
+                <div class="comment-box-author"><span class="uss-css-user-username">ghost</span></div>
+                <div class="comment-box-date">2 hours ago</div>
+                <div class="comment-box-content"><p>Hello</p></div>
+                <div class="comment-box-reply">
+                    <div class="comment-box-author"><span class="uss-css-user-username">nnie</span></div>
+                    <div class="comment-box-date">Yesterday</div>
+                    <div class="comment-box-content"><p>Hi</p></div>
+                </div>
+            </div>"#,
+        );
+        let parse_at = |fetched_at| {
+            let dates = DateContext {
+                fetched_at,
+                ..SNAPSHOT_DATES
+            };
+            parse_page(
+                &page,
+                PageKind::Article,
+                std::slice::from_ref(default_profile()),
+                &dates,
+            )
+            .unwrap()
+        };
+        let first = parse_at(datetime!(2024-08-08 13:43 UTC));
+        let second = parse_at(datetime!(2024-08-09 18:02 UTC));
+        let identities = |article: &Article| {
+            article
+                .all_comments()
+                .map(|c| c.identity.clone())
+                .collect::<Vec<_>>()
+        };
+        assert_ne!(
+            first.comments[0].comment.comment_datetime,
+            second.comments[0].comment.comment_datetime
+        );
+        assert_eq!(identities(&first), identities(&second));
+        assert_eq!(identities(&first)[0], "name:ghost@~185f8db3");
+    }
+
+    #[test]
+    fn test_parse_page_missing_world_class() {
+        let page = minimal_page("").replace("world-e69d6a36", "nothing-e69d6a36");
+        assert!(matches!(parse(&page), Err(ParseError::MissingWorldClass)));
+    }
+
+    #[test]
+    fn test_parse_paginated_comments() {
+        let first = fs::read_to_string("fixtures/paginated-page-1.htm").unwrap();
+        let second = fs::read_to_string("fixtures/paginated-page-2.htm")
+            .unwrap()
+            .replace("Second newest comment.", "Second newest comment, edited.");
+        let mut article = parse(&first).unwrap();
+        assert_eq!(article.comments.len(), 2);
+        assert_eq!(article.next_page.as_deref(), Some("?comments_page=2"));
+
+        let page = parse_comments_page(&second, default_profile(), &SNAPSHOT_DATES);
+        assert_eq!(page.next_page, None);
+        article.merge(page);
+        // The thread that shifted onto the second page is not duplicated, though it was edited.
+        let identities: Vec<_> = article
+            .comments
+            .iter()
+            .map(|thread| thread.comment.identity.as_str())
+            .collect();
+        assert_eq!(identities, ["3003", "3002", "3001"]);
+        assert_eq!(article.comments[2].comment.author_name, "skairunner");
+        assert_eq!(article.next_page, None);
+        assert!(article.warnings.is_empty());
+    }
+
+    /// Comments by the same author in the same minute may land on different pages.
+    #[test]
+    fn test_merge_same_minute_comments() {
+        let thread = |content: &str, reply: &str| {
+            format!(
+                r#"<div class="comment-box">
+                <div class="comment-box-author"><span class="uss-css-user-username">ghost</span></div>
+                <div class="comment-box-date">Aug 8, 2024 13:43</div>
+                <div class="comment-box-content"><p>{content}</p></div>
+                <div class="comment-box-reply">
+                    <div class="comment-box-author"><span class="uss-css-user-username">nnie</span></div>
+                    <div class="comment-box-date">Aug 8, 2024 13:50</div>
+                    <div class="comment-box-content"><p>{reply}</p></div>
+                </div>
+            </div>"#
+            )
+        };
+        let identities = |article: &Article| {
+            article
+                .all_comments()
+                .map(|c| c.identity.clone())
+                .collect::<Vec<_>>()
+        };
+        let mut article = parse(&minimal_page(&thread("Hello", "Hi"))).unwrap();
+        // The first thread shifted onto the second page too.
+        let second = [thread("Hello", "Hi"), thread("Hello again", "Hi again")].join("\n");
+        article.merge(parse_comments_page(
+            &minimal_page(&second),
+            default_profile(),
+            &SNAPSHOT_DATES,
+        ));
+        assert_eq!(article.comments.len(), 2);
+        assert_eq!(identities(&article).iter().unique().count(), 4);
+        // The identities are the same as if all comments were on one page.
+        let whole = [thread("Hello", "Hi"), thread("Hello again", "Hi again")].join("\n");
+        let whole = parse(&minimal_page(&whole)).unwrap();
+        assert_eq!(identities(&article), identities(&whole));
+    }
+
+    #[test]
+    fn test_parse_count() {
+        assert_eq!(parse_count("97"), Some(97));
+        assert_eq!(parse_count(" 1,234 "), Some(1234));
+        assert_eq!(parse_count("1.2k"), Some(1200));
+        assert_eq!(parse_count("none"), None);
+    }
+
+    #[test]
+    fn test_parse_world_homepage() {
+        let fixture = fs::read_to_string("fixtures/world-homepage-page.htm").unwrap();
+        let profiles = std::slice::from_ref(default_profile());
+        let page = parse_page(&fixture, PageKind::World, profiles, &SNAPSHOT_DATES).unwrap();
+        assert_eq!(page.title, "Solaris");
+        // The world homepage is identified by the world itself.
+        assert_eq!(
+            page.worldanvil_id.as_deref(),
+            Some("e69d6a36-2d22-4bf2-80f9-456a9b0d909e")
+        );
+        assert_eq!(page.comments.len(), 1);
+        assert_eq!(page.comments[0].replies.len(), 1);
+        let page = parse_page(&fixture, PageKind::Map, profiles, &SNAPSHOT_DATES).unwrap();
+        assert_eq!(page.worldanvil_id, None);
+        // It is not an article, so it does not parse as one.
+        assert!(matches!(
+            parse_page(&fixture, PageKind::Article, profiles, &SNAPSHOT_DATES),
+            Err(ParseError::NoHeader)
+        ));
+    }
+
+    #[test]
+    fn test_parse_page_profile_fallback() {
+        let fixture = fs::read_to_string("fixtures/example-solaris-page.htm").unwrap();
+        let redesign = SelectorProfile {
+            name: "redesign".to_string(),
+            visual_container: "#new-layout".to_string(),
+            ..Default::default()
+        };
+        let profiles = [redesign.compile().unwrap(), default_profile().clone()];
+        let article = parse_page(&fixture, PageKind::Article, &profiles, &SNAPSHOT_DATES).unwrap();
+        assert_eq!(article.profile, "default v1");
+        assert_eq!(article.comments.len(), 3);
+        // Only the first profile's error is reported when every profile fails.
+        assert!(matches!(
+            parse_page(&fixture, PageKind::Article, &profiles[..1], &SNAPSHOT_DATES),
+            Err(ParseError::NoVisualContainer)
+        ));
+        assert!(matches!(
+            parse_page(&fixture, PageKind::Article, &[], &SNAPSHOT_DATES),
+            Err(ParseError::NoProfiles)
+        ));
+    }
+
+    /// A profile that finds the article but none of its comments is not trusted with the page.
+    #[test]
+    fn test_parse_page_profile_without_comments() {
+        let fixture = fs::read_to_string("fixtures/example-solaris-page.htm").unwrap();
+        let stale = SelectorProfile {
+            name: "stale".to_string(),
+            comment: ".old-comment-box".to_string(),
+            ..Default::default()
+        };
+        let profiles = [stale.compile().unwrap(), default_profile().clone()];
+        let article = parse_page(&fixture, PageKind::Article, &profiles, &SNAPSHOT_DATES).unwrap();
+        assert_eq!(article.profile_index, 1);
+        assert_eq!(article.comments.len(), 3);
+        assert!(matches!(
+            parse_page(&fixture, PageKind::Article, &profiles[..1], &SNAPSHOT_DATES),
+            Err(ParseError::CommentsNotFound)
+        ));
+        // Pages that really have no comments are still read by it.
+        let page = minimal_page(r#"<div class="extendedbody-comments"><h2>Comments</h2></div>"#);
+        let article = parse_page(&page, PageKind::Article, &profiles, &SNAPSHOT_DATES).unwrap();
+        assert_eq!(article.profile_index, 0);
+        assert!(article.comments.is_empty());
+    }
+}
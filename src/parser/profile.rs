@@ -0,0 +1,245 @@
+//! Selector profiles describe where the parser finds things on a WorldAnvil page,
+//! so that markup changes can be handled by editing a file instead of redeploying.
+use dotenv::var as envvar;
+use lazy_static::lazy_static;
+use scraper::Selector;
+use serde::{Deserialize, Serialize};
+use std::fs;
+
+#[derive(thiserror::Error, Debug)]
+pub enum ProfileError {
+    #[error("could not read selector profiles from {path}: {source}")]
+    Io {
+        path: String,
+        source: std::io::Error,
+    },
+    #[error("could not parse selector profiles in {path}: {source}")]
+    Json {
+        path: String,
+        source: serde_json::Error,
+    },
+    #[error("invalid {field} selector '{selector}' in profile {profile}: {message}")]
+    BadSelector {
+        profile: String,
+        field: &'static str,
+        selector: String,
+        message: String,
+    },
+}
+
+/// The CSS selectors and class prefixes used to parse a page.
+/// Fields missing from a profile file take the built-in default.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(default)]
+pub struct SelectorProfile {
+    pub name: String,
+    /// Bumped whenever the profile is changed so logs show which revision parsed a page.
+    pub version: u32,
+    pub visual_container: String,
+    pub world_class_prefix: String,
+    pub title: String,
+    /// The title of pages other than articles, such as the world homepage or a timeline.
+    pub page_title: String,
+    pub article_main: String,
+    pub article_class_prefix: String,
+    pub comment: String,
+    pub reply: String,
+    pub author_class_prefix: String,
+    pub author_name: String,
+    pub author_avatar: String,
+    pub date: String,
+    pub content: String,
+    pub next_comments_page: String,
+    /// Labels of the article metadata table, each followed by its value.
+    pub metadata_label: String,
+    pub tag: String,
+    pub comment_section: String,
+    pub comments_disabled: String,
+    /// Markers of pages that are not a readable article.
+    pub login_form: String,
+    pub subscriber_gate: String,
+    pub maintenance: String,
+    /// Markers of WorldAnvil's page for articles that do not exist.
+    pub not_found: String,
+    pub canonical_link: String,
+}
+
+impl Default for SelectorProfile {
+    /// The markup WorldAnvil used when the parser was written.
+    fn default() -> Self {
+        Self {
+            name: "default".to_string(),
+            version: 1,
+            visual_container: "#visual-container".to_string(),
+            world_class_prefix: "world".to_string(),
+            title: "#content .article-title h1".to_string(),
+            page_title: "#content h1, .page-title h1".to_string(),
+            article_main: ".page-article-main".to_string(),
+            article_class_prefix: "article".to_string(),
+            comment: ".comment-box".to_string(),
+            reply: ".comment-box-reply".to_string(),
+            author_class_prefix: "comment-author".to_string(),
+            author_name: "span.uss-css-user-username".to_string(),
+            author_avatar: "div.comment-box-avatar .img-avatar".to_string(),
+            date: ".comment-box-date".to_string(),
+            content: ".comment-box-content".to_string(),
+            next_comments_page:
+                ".comments-pagination a[rel~=next], [data-comments-next], a.load-more-comments"
+                    .to_string(),
+            metadata_label: ".metadata-label".to_string(),
+            tag: "[data-tag]".to_string(),
+            comment_section: ".extendedbody-comments".to_string(),
+            comments_disabled: ".comments-disabled, [data-comments-disabled]".to_string(),
+            login_form: r#"form[action*="login"] input[type="password"]"#.to_string(),
+            subscriber_gate: ".subscriber-gate, .subscribers-only, .subscription-required"
+                .to_string(),
+            maintenance: ".maintenance-page, #maintenance".to_string(),
+            not_found: ".error-404, .page-404, .article-not-found".to_string(),
+            canonical_link: r#"link[rel="canonical"]"#.to_string(),
+        }
+    }
+}
+
+/// A selector profile with its selectors parsed, ready to be used by the parser.
+#[derive(Clone, Debug)]
+pub struct CompiledProfile {
+    pub name: String,
+    pub version: u32,
+    pub visual_container: Selector,
+    pub world_class_prefix: String,
+    pub title: Selector,
+    pub page_title: Selector,
+    pub article_main: Selector,
+    pub article_class_prefix: String,
+    pub comment: Selector,
+    pub reply: Selector,
+    pub author_class_prefix: String,
+    pub author_name: Selector,
+    pub author_avatar: Selector,
+    pub date: Selector,
+    pub content: Selector,
+    pub next_comments_page: Selector,
+    pub metadata_label: Selector,
+    pub tag: Selector,
+    pub comment_section: Selector,
+    pub comments_disabled: Selector,
+    pub login_form: Selector,
+    pub subscriber_gate: Selector,
+    pub maintenance: Selector,
+    pub not_found: Selector,
+    pub canonical_link: Selector,
+}
+
+impl CompiledProfile {
+    /// How the profile is referred to in logs, e.g. "default v1".
+    pub fn label(&self) -> String {
+        format!("{} v{}", self.name, self.version)
+    }
+}
+
+lazy_static! {
+    static ref DEFAULT_PROFILE: CompiledProfile = SelectorProfile::default()
+        .compile()
+        .expect("the built-in selector profile is valid");
+}
+
+/// The built-in profile, compiled only once.
+pub fn default_profile() -> &'static CompiledProfile {
+    &DEFAULT_PROFILE
+}
+
+impl SelectorProfile {
+    pub fn compile(&self) -> Result<CompiledProfile, ProfileError> {
+        let selector = |field: &'static str, selector: &str| {
+            Selector::parse(selector).map_err(|e| ProfileError::BadSelector {
+                profile: format!("{} v{}", self.name, self.version),
+                field,
+                selector: selector.to_string(),
+                message: e.to_string(),
+            })
+        };
+        Ok(CompiledProfile {
+            name: self.name.clone(),
+            version: self.version,
+            visual_container: selector("visual_container", &self.visual_container)?,
+            world_class_prefix: self.world_class_prefix.clone(),
+            title: selector("title", &self.title)?,
+            page_title: selector("page_title", &self.page_title)?,
+            article_main: selector("article_main", &self.article_main)?,
+            article_class_prefix: self.article_class_prefix.clone(),
+            comment: selector("comment", &self.comment)?,
+            reply: selector("reply", &self.reply)?,
+            author_class_prefix: self.author_class_prefix.clone(),
+            author_name: selector("author_name", &self.author_name)?,
+            author_avatar: selector("author_avatar", &self.author_avatar)?,
+            date: selector("date", &self.date)?,
+            content: selector("content", &self.content)?,
+            next_comments_page: selector("next_comments_page", &self.next_comments_page)?,
+            metadata_label: selector("metadata_label", &self.metadata_label)?,
+            tag: selector("tag", &self.tag)?,
+            comment_section: selector("comment_section", &self.comment_section)?,
+            comments_disabled: selector("comments_disabled", &self.comments_disabled)?,
+            login_form: selector("login_form", &self.login_form)?,
+            subscriber_gate: selector("subscriber_gate", &self.subscriber_gate)?,
+            maintenance: selector("maintenance", &self.maintenance)?,
+            not_found: selector("not_found", &self.not_found)?,
+            canonical_link: selector("canonical_link", &self.canonical_link)?,
+        })
+    }
+}
+
+/// Parse a JSON list of profiles, to be tried in order.
+/// The built-in default is tried last unless the list has its own "default" profile.
+pub fn profiles_from_json(path: &str, json: &str) -> Result<Vec<CompiledProfile>, ProfileError> {
+    let profiles: Vec<SelectorProfile> =
+        serde_json::from_str(json).map_err(|source| ProfileError::Json {
+            path: path.to_string(),
+            source,
+        })?;
+    let has_default = profiles.iter().any(|profile| profile.name == "default");
+    let mut compiled = profiles
+        .iter()
+        .map(SelectorProfile::compile)
+        .collect::<Result<Vec<_>, _>>()?;
+    if !has_default {
+        compiled.push(default_profile().clone());
+    }
+    Ok(compiled)
+}
+
+/// Load the profiles from the file named by SELECTOR_PROFILES, or just the built-in default.
+pub fn load_profiles() -> Result<Vec<CompiledProfile>, ProfileError> {
+    let Ok(path) = envvar("SELECTOR_PROFILES") else {
+        return Ok(vec![default_profile().clone()]);
+    };
+    let json = fs::read_to_string(&path).map_err(|source| ProfileError::Io {
+        path: path.clone(),
+        source,
+    })?;
+    profiles_from_json(&path, &json)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_profiles_from_json() {
+        let profiles = profiles_from_json(
+            "profiles.json",
+            r#"[{"name": "redesign", "version": 3, "comment": ".comment-card"}]"#,
+        )
+        .unwrap();
+        let labels: Vec<_> = profiles.iter().map(CompiledProfile::label).collect();
+        assert_eq!(labels, ["redesign v3", "default v1"]);
+        // Unspecified selectors fall back to the default ones.
+        assert_eq!(profiles[0].author_class_prefix, "comment-author");
+
+        let error = profiles_from_json("profiles.json", r#"[{"name": "broken", "date": "..."}]"#)
+            .unwrap_err();
+        assert!(matches!(
+            error,
+            ProfileError::BadSelector { field: "date", .. }
+        ));
+    }
+}
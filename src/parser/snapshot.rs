@@ -0,0 +1,191 @@
+//! Snapshots of parser output for the pages saved in the fixtures directory.
+//! Every `fixtures/<name>.htm` has the expected parse of it in `fixtures/<name>.json`.
+//! Pages are parsed as articles unless their name starts with another page kind, e.g. `map-`.
+//! Pages named `<name>-page-<n>` with n above 1 are further pages of comments.
+use crate::db::schema::PageKind;
+use crate::parser::date::DateContext;
+use crate::parser::profile::CompiledProfile;
+use crate::parser::{parse_comments_page, parse_page};
+use chrono_tz::Tz;
+use serde_json::json;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use time::macros::datetime;
+
+pub const FIXTURES_DIR: &str = "fixtures";
+
+/// Saved pages are parsed as if fetched at this moment, so relative dates stay stable.
+pub const SNAPSHOT_DATES: DateContext = DateContext {
+    fetched_at: datetime!(2025-06-01 12:00 UTC),
+    timezone: Tz::UTC,
+};
+
+/// Where the snapshot of a saved page lives.
+pub fn snapshot_path(page: &Path) -> PathBuf {
+    page.with_extension("json")
+}
+
+/// What a saved page is.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FixtureKind {
+    /// A whole page of the given kind.
+    Page(PageKind),
+    /// A further page of an article's comments, read the way the updater reads them.
+    CommentsPage,
+}
+
+/// What a saved page is, going by its name.
+pub fn fixture_kind(page: &Path) -> FixtureKind {
+    let name = page
+        .file_stem()
+        .map(|name| name.to_string_lossy())
+        .unwrap_or_default();
+    let page_number = name
+        .rsplit_once("-page-")
+        .and_then(|(_, number)| number.parse::<u32>().ok());
+    if page_number.is_some_and(|number| number > 1) {
+        return FixtureKind::CommentsPage;
+    }
+    let kinds = [
+        PageKind::World,
+        PageKind::Timeline,
+        PageKind::Map,
+        PageKind::Manuscript,
+    ];
+    kinds
+        .into_iter()
+        .find(|kind| name.starts_with(&format!("{kind}-")))
+        .map_or(FixtureKind::Page(PageKind::Article), FixtureKind::Page)
+}
+
+/// All saved pages in the directory, sorted by name.
+pub fn corpus_pages(dir: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut pages = vec![];
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.extension().is_some_and(|ext| ext == "htm") {
+            pages.push(path);
+        }
+    }
+    pages.sort();
+    Ok(pages)
+}
+
+/// Render the parse of a page as pretty JSON. Pages that fail to parse record the error instead.
+/// Further pages of comments are read with the first profile.
+pub fn render_snapshot(page_body: &str, kind: FixtureKind, profiles: &[CompiledProfile]) -> String {
+    let value = match (kind, profiles.first()) {
+        (FixtureKind::Page(kind), _) => {
+            match parse_page(page_body, kind, profiles, &SNAPSHOT_DATES) {
+                Ok(article) => serde_json::to_value(article).expect("articles serialize to json"),
+                Err(e) => json!({ "error": e.to_string() }),
+            }
+        }
+        (FixtureKind::CommentsPage, Some(profile)) => {
+            let page = parse_comments_page(page_body, profile, &SNAPSHOT_DATES);
+            serde_json::to_value(page).expect("comment pages serialize to json")
+        }
+        (FixtureKind::CommentsPage, None) => json!({ "error": "no selector profiles" }),
+    };
+    let mut rendered = serde_json::to_string_pretty(&value).expect("json values serialize");
+    rendered.push('\n');
+    rendered
+}
+
+/// A line diff between two snapshots, with removed lines prefixed by '-' and added ones by '+'.
+/// Returns an empty string if they are the same.
+pub fn diff_lines(old: &str, new: &str) -> String {
+    let old: Vec<_> = old.lines().collect();
+    let new: Vec<_> = new.lines().collect();
+    // Longest common subsequence lengths of every pair of suffixes.
+    let mut lcs = vec![vec![0usize; new.len() + 1]; old.len() + 1];
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            lcs[i][j] = if old[i] == new[j] {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                lcs[i + 1][j].max(lcs[i][j + 1])
+            };
+        }
+    }
+    let mut diff = String::new();
+    let (mut i, mut j) = (0, 0);
+    while i < old.len() || j < new.len() {
+        if i < old.len() && j < new.len() && old[i] == new[j] {
+            i += 1;
+            j += 1;
+        } else if j < new.len() && (i == old.len() || lcs[i][j + 1] > lcs[i + 1][j]) {
+            diff.push_str(&format!("+{}\n", new[j]));
+            j += 1;
+        } else {
+            diff.push_str(&format!("-{}\n", old[i]));
+            i += 1;
+        }
+    }
+    diff
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::parser::profile::default_profile;
+
+    /// Every saved page must still parse exactly as its snapshot says.
+    /// Run `cargo run --bin snapshot -- <name>` to review and accept changes.
+    #[test]
+    fn test_corpus_snapshots() {
+        let profiles = std::slice::from_ref(default_profile());
+        let pages = corpus_pages(Path::new(FIXTURES_DIR)).unwrap();
+        assert!(!pages.is_empty());
+        for page in pages {
+            let body = fs::read_to_string(&page).unwrap();
+            let snapshot_path = snapshot_path(&page);
+            let expected = fs::read_to_string(&snapshot_path)
+                .unwrap_or_else(|_| panic!("{} has no snapshot", page.display()));
+            let rendered = render_snapshot(&body, fixture_kind(&page), profiles);
+            let diff = diff_lines(&expected, &rendered);
+            assert!(
+                diff.is_empty(),
+                "{} changed:\n{diff}",
+                snapshot_path.display()
+            );
+        }
+    }
+
+    #[test]
+    fn test_fixture_kind() {
+        let kind = |name: &str| fixture_kind(Path::new(FIXTURES_DIR).join(name).as_path());
+        assert_eq!(
+            kind("world-homepage-page.htm"),
+            FixtureKind::Page(PageKind::World)
+        );
+        assert_eq!(
+            kind("timeline-history-page.htm"),
+            FixtureKind::Page(PageKind::Timeline)
+        );
+        assert_eq!(
+            kind("map-terminator-page.htm"),
+            FixtureKind::Page(PageKind::Map)
+        );
+        assert_eq!(
+            kind("manuscript-first-light-page.htm"),
+            FixtureKind::Page(PageKind::Manuscript)
+        );
+        assert_eq!(
+            kind("timezone-new-york-page.htm"),
+            FixtureKind::Page(PageKind::Article)
+        );
+        assert_eq!(
+            kind("paginated-page-1.htm"),
+            FixtureKind::Page(PageKind::Article)
+        );
+        assert_eq!(kind("paginated-page-2.htm"), FixtureKind::CommentsPage);
+    }
+
+    #[test]
+    fn test_diff_lines() {
+        assert_eq!(diff_lines("a\nb\nc\n", "a\nb\nc\n"), "");
+        assert_eq!(diff_lines("a\nb\nc\n", "a\nx\nc\nd\n"), "-b\n+x\n+d\n");
+    }
+}
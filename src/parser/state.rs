@@ -0,0 +1,237 @@
+//! Working out what kind of page WorldAnvil sent back before trying to read comments from it.
+use crate::db::schema::PageKind;
+use crate::parser::date::DateContext;
+use crate::parser::profile::CompiledProfile;
+use crate::parser::{parse_comments, parse_html, Article, CommentsPage, FetchedPage, ParseError};
+use chrono_tz::Tz;
+use reqwest::{StatusCode, Url};
+use scraper::Html;
+
+/// What a fetched article page turned out to be.
+pub enum PageState {
+    Ok(Article),
+    /// The article was deleted.
+    NotFound,
+    /// Only logged in users with access to the world can see the article.
+    Private,
+    /// Only subscribers of the world can see the article.
+    SubscriberGated,
+    /// The article is readable, but nobody can comment on it.
+    CommentsDisabled(Article),
+    /// The article now lives at another url, e.g. because its slug changed.
+    Redirected {
+        canonical_url: String,
+    },
+    /// WorldAnvil is down for maintenance.
+    Maintenance,
+    /// The page is the same as when it was last fetched.
+    NotModified,
+}
+
+impl PageState {
+    pub fn name(&self) -> &'static str {
+        match self {
+            PageState::Ok(_) => "ok",
+            PageState::NotFound => "not found",
+            PageState::Private => "private",
+            PageState::SubscriberGated => "subscriber gated",
+            PageState::CommentsDisabled(_) => "comments disabled",
+            PageState::Redirected { .. } => "redirected",
+            PageState::Maintenance => "maintenance",
+            PageState::NotModified => "not modified",
+        }
+    }
+}
+
+/// Classify a page fetched from `requested_url`, parsing it if it is an article.
+/// A ParseError means the page is none of the pages WorldAnvil is known to send instead of an
+/// article, but could not be read as one either.
+/// Dates are read in `timezone` if the page does not say which one it uses.
+pub fn classify_page(
+    page: &FetchedPage,
+    requested_url: &str,
+    kind: PageKind,
+    profiles: &[CompiledProfile],
+    timezone: Tz,
+) -> Result<PageState, ParseError> {
+    match page.status {
+        StatusCode::NOT_MODIFIED => return Ok(PageState::NotModified),
+        StatusCode::NOT_FOUND | StatusCode::GONE => return Ok(PageState::NotFound),
+        StatusCode::SERVICE_UNAVAILABLE => return Ok(PageState::Maintenance),
+        _ => {}
+    }
+    let html = Html::parse_document(&page.body);
+    let has = |select: fn(&CompiledProfile) -> &scraper::Selector| {
+        profiles
+            .iter()
+            .any(|profile| html.select(select(profile)).next().is_some())
+    };
+    if has(|profile| &profile.maintenance) {
+        return Ok(PageState::Maintenance);
+    }
+    if has(|profile| &profile.not_found) {
+        return Ok(PageState::NotFound);
+    }
+    if page.url.path().starts_with("/login") {
+        return Ok(PageState::Private);
+    }
+    if has(|profile| &profile.subscriber_gate) {
+        return Ok(PageState::SubscriberGated);
+    }
+    let canonical_url = moved_to(page, &html, requested_url, profiles);
+    let dates = DateContext {
+        fetched_at: page.fetched_at,
+        timezone,
+    };
+    match (parse_html(&html, kind, profiles, &dates), canonical_url) {
+        (Ok(_), Some(canonical_url)) => Ok(PageState::Redirected { canonical_url }),
+        (Ok(article), None) if article.metadata.comments_disabled => {
+            Ok(PageState::CommentsDisabled(article))
+        }
+        (Ok(article), None) => Ok(PageState::Ok(article)),
+        (Err(ParseError::NoVisualContainer), _) if has(|profile| &profile.login_form) => {
+            Ok(PageState::Private)
+        }
+        (Err(e), _) => Err(e),
+    }
+}
+
+/// Read a further page of comments, unless WorldAnvil sent back something else, such as an
+/// error or the maintenance page. Says what the page was instead.
+pub fn classify_comments_page(
+    page: &FetchedPage,
+    profile: &CompiledProfile,
+    dates: &DateContext,
+) -> Result<CommentsPage, String> {
+    if !page.status.is_success() {
+        return Err(format!("WorldAnvil answered {}", page.status));
+    }
+    let html = Html::parse_document(&page.body);
+    if html.select(&profile.maintenance).next().is_some() {
+        return Err("WorldAnvil is down for maintenance".to_string());
+    }
+    if html.select(&profile.not_found).next().is_some() {
+        return Err("the page does not exist".to_string());
+    }
+    Ok(parse_comments(&html, profile, dates))
+}
+
+fn requested_path(requested_url: &str) -> String {
+    Url::parse(requested_url)
+        .map(|url| url.path().to_string())
+        .unwrap_or_default()
+}
+
+/// The url the article has moved to, either by redirect or according to its canonical link.
+/// Only the path is compared, as WorldAnvil links are not consistent about http and https.
+fn moved_to(
+    page: &FetchedPage,
+    html: &Html,
+    requested_url: &str,
+    profiles: &[CompiledProfile],
+) -> Option<String> {
+    let requested = requested_path(requested_url);
+    let same_path = |url: &Url| url.path().trim_end_matches('/') == requested.trim_end_matches('/');
+    if !same_path(&page.url) {
+        let mut url = page.url.clone();
+        url.set_fragment(None);
+        return Some(url.to_string());
+    }
+    profiles
+        .iter()
+        .flat_map(|profile| html.select(&profile.canonical_link))
+        .filter_map(|link| link.attr("href"))
+        .filter_map(|href| page.url.join(href).ok())
+        .find(|url| !same_path(url))
+        .map(|url| url.to_string())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::parser::profile::default_profile;
+    use std::fs;
+    use time::OffsetDateTime;
+
+    const URL: &str = "https://www.worldanvil.com/w/solaris-nnie/a/chewpaper-material";
+
+    fn classify(status: StatusCode, url: &str, body: &str) -> Result<PageState, ParseError> {
+        let page = FetchedPage {
+            status,
+            url: Url::parse(url).unwrap(),
+            fetched_at: OffsetDateTime::now_utc(),
+            body: body.to_string(),
+            validators: Default::default(),
+        };
+        classify_page(
+            &page,
+            URL,
+            PageKind::Article,
+            std::slice::from_ref(default_profile()),
+            Tz::UTC,
+        )
+    }
+
+    #[test]
+    fn test_classify_page() {
+        let article = fs::read_to_string("fixtures/example-solaris-page.htm").unwrap();
+        let disabled = fs::read_to_string("fixtures/comments-disabled-page.htm").unwrap();
+        let login =
+            r#"<html><body><form action="/login"><input type="password"></form></body></html>"#;
+        let gated = article.replace(
+            r#"<div id="full-layout""#,
+            r#"<div class="subscriber-gate">Subscribe to read</div><div id="full-layout""#,
+        );
+        let moved = "https://www.worldanvil.com/w/solaris-nnie/a/chewing-paper-material";
+        let cases = [
+            (StatusCode::OK, URL, article.as_str(), "ok"),
+            (StatusCode::NOT_FOUND, URL, "", "not found"),
+            (StatusCode::NOT_MODIFIED, URL, "", "not modified"),
+            (StatusCode::SERVICE_UNAVAILABLE, URL, "", "maintenance"),
+            (
+                StatusCode::OK,
+                URL,
+                r#"<div class="maintenance-page">Back soon</div>"#,
+                "maintenance",
+            ),
+            (
+                StatusCode::OK,
+                "https://www.worldanvil.com/login",
+                login,
+                "private",
+            ),
+            (StatusCode::OK, URL, login, "private"),
+            (StatusCode::OK, URL, gated.as_str(), "subscriber gated"),
+            (StatusCode::OK, URL, disabled.as_str(), "comments disabled"),
+            (StatusCode::OK, moved, article.as_str(), "redirected"),
+            (
+                StatusCode::OK,
+                "https://www.worldanvil.com/w/solaris-nnie",
+                r#"<div class="error-404">This article does not exist</div>"#,
+                "not found",
+            ),
+        ];
+        for (status, url, body, expected) in cases {
+            let state = classify(status, url, body).unwrap();
+            assert_eq!(state.name(), expected, "{status} {url}");
+        }
+        match classify(StatusCode::OK, moved, &article).unwrap() {
+            PageState::Redirected { canonical_url } => assert_eq!(canonical_url, moved),
+            _ => unreachable!(),
+        }
+        // Neither mentioning maintenance nor being redirected says enough about a page.
+        for (url, body) in [
+            (URL, "<p>Something else</p>"),
+            (URL, "<p>Down for maintenance</p>"),
+            (
+                "https://www.worldanvil.com/w/solaris-nnie",
+                "<p>World homepage</p>",
+            ),
+        ] {
+            assert!(matches!(
+                classify(StatusCode::OK, url, body),
+                Err(ParseError::NoVisualContainer)
+            ));
+        }
+    }
+}
@@ -1,7 +1,11 @@
 use crate::err::AppError;
-use reqwest::header::{HeaderMap, HeaderValue, ACCEPT_LANGUAGE, HOST};
+use dotenv::var as envvar;
+use lazy_static::lazy_static;
+use reqwest::header::{HeaderMap, HeaderValue, HOST};
 use reqwest::{Client, ClientBuilder, Url};
 use std::env;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
 
 static USER_AGENT: &str = concat!(
     "commentater (commentater.skye.im, ",
@@ -30,6 +34,82 @@ pub fn get_default_reqwest() -> Client {
     get_client_builder().build().unwrap()
 }
 
+/// A token bucket that lets through `rate` requests per second on average,
+/// and up to `burst` at once after it has been idle.
+pub struct RateLimiter {
+    rate: f64,
+    burst: f64,
+    bucket: Mutex<Bucket>,
+}
+
+struct Bucket {
+    tokens: f64,
+    refilled_at: Instant,
+}
+
+impl RateLimiter {
+    pub fn new(rate: f64, burst: f64) -> Self {
+        Self {
+            rate,
+            burst,
+            bucket: Mutex::new(Bucket {
+                tokens: burst,
+                refilled_at: Instant::now(),
+            }),
+        }
+    }
+
+    /// Take a token, returning how long to wait before it may be used.
+    /// Tokens are handed out in the order they are asked for, even when the bucket is empty.
+    fn reserve(&self, now: Instant) -> Duration {
+        let mut bucket = self.bucket.lock().unwrap();
+        let elapsed = now.saturating_duration_since(bucket.refilled_at);
+        bucket.tokens = (bucket.tokens + elapsed.as_secs_f64() * self.rate).min(self.burst);
+        bucket.refilled_at = now;
+        bucket.tokens -= 1.0;
+        if bucket.tokens >= 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(-bucket.tokens / self.rate)
+        }
+    }
+
+    /// Wait until a request may be sent.
+    pub async fn acquire(&self) {
+        let wait = self.reserve(Instant::now());
+        if !wait.is_zero() {
+            tokio::time::sleep(wait).await;
+        }
+    }
+}
+
+lazy_static! {
+    static ref PAGE_CLIENT: Client = get_default_reqwest();
+    static ref PAGE_RATE_LIMITER: RateLimiter = {
+        let setting = |name, default| {
+            envvar(name)
+                .ok()
+                .and_then(|value| value.parse().ok())
+                .filter(|value: &f64| *value > 0.0)
+                .unwrap_or(default)
+        };
+        RateLimiter::new(
+            setting("WORLDANVIL_REQUESTS_PER_SEC", 2.0),
+            setting("WORLDANVIL_REQUEST_BURST", 5.0),
+        )
+    };
+}
+
+/// The client pages are fetched with. It is shared so connections to WorldAnvil are reused.
+pub fn page_client() -> &'static Client {
+    &PAGE_CLIENT
+}
+
+/// The limiter every page fetch from WorldAnvil waits on, however many workers are running.
+pub fn page_rate_limiter() -> &'static RateLimiter {
+    &PAGE_RATE_LIMITER
+}
+
 pub fn check_url_valid(url: &str) -> Result<(), AppError> {
     let url = Url::parse(url)?;
     match url.domain() {
@@ -45,3 +125,21 @@ pub fn check_url_valid(url: &str) -> Result<(), AppError> {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_rate_limiter() {
+        let limiter = RateLimiter::new(2.0, 2.0);
+        let start = Instant::now();
+        let waits: Vec<_> = (0..4).map(|_| limiter.reserve(start)).collect();
+        let ms = |ms| Duration::from_millis(ms);
+        assert_eq!(waits, [ms(0), ms(0), ms(500), ms(1000)]);
+        // Once the backlog has been waited out the bucket fills up again, but never past the burst.
+        let later = start + Duration::from_secs(10);
+        let waits: Vec<_> = (0..3).map(|_| limiter.reserve(later)).collect();
+        assert_eq!(waits, [ms(0), ms(0), ms(500)]);
+    }
+}
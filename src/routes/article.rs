@@ -1,26 +1,44 @@
 use crate::auth::UserState;
 use crate::db::article::{
-    get_article, get_article_conn, get_article_details, get_unqueued_article_ids, register_articles,
+    get_article_conn, get_article_details, get_unqueued_article_ids, register_pages,
 };
-use crate::db::comments::get_comments;
-use crate::db::queue::{article_is_queued, insert_tasks};
+use crate::db::comments::{get_comments, get_replies};
+use crate::db::queue::{
+    cancel_task, cancel_world_tasks, get_user_queue_settings, insert_tasks, retry_dead_task,
+    QueueDefaults,
+};
+use crate::db::schema::{CommentThread, PageInsert, PageKind, TaskPriority, World};
 use crate::db::user::get_user;
 use crate::db::world::get_world;
 use crate::err::AppError;
+use crate::parser::content::normalize_mention;
 use crate::req::get_wa_client_builder;
 use crate::templates::TEMPLATES;
-use crate::worldanvil_api::world_list_articles;
-use axum::extract::{Path, State};
+use crate::worldanvil_api::schema::WorldEntity;
+use crate::worldanvil_api::{
+    world_list_articles, world_list_manuscripts, world_list_maps, world_list_timelines,
+};
+use axum::extract::{Path, Query, State};
 use axum::response::{Html, IntoResponse, Redirect, Response};
+use serde::Deserialize;
 use sqlx::{Acquire, PgPool};
+use std::collections::HashMap;
 use tera::Context;
 
+/// Show only what mentions the user or asks a question.
+#[derive(Deserialize)]
+pub struct PriorityFilter {
+    #[serde(default)]
+    pub priority: bool,
+}
+
 pub async fn list_comments(
     State(pool): State<PgPool>,
     Path((world_id, article_id)): Path<(i64, i64)>,
+    Query(filter): Query<PriorityFilter>,
     user_state: UserState,
 ) -> Result<Response, AppError> {
-    let user_id = match user_state.user_id.clone() {
+    let user_id = match user_state.user_id {
         Some(id) => id,
         None => return Ok(Redirect::to("/login").into_response()),
     };
@@ -31,7 +49,34 @@ pub async fn list_comments(
     let article = get_article_details(&pool, &article_id, &user_id).await?;
     context.insert("article", &article);
     let comments = get_comments(&pool, article_id, user_id).await?;
-    context.insert("comments", &comments);
+    let mut replies: HashMap<i64, Vec<_>> = HashMap::new();
+    for reply in get_replies(&pool, article_id, user_id).await? {
+        replies.entry(reply.parent).or_default().push(reply);
+    }
+    let user_name = user_state.user_name.as_deref();
+    let mut threads: Vec<_> = comments
+        .into_iter()
+        .map(|comment| {
+            let thread_replies = replies.remove(&comment.id).unwrap_or_default();
+            CommentThread::new(comment, thread_replies, user_name)
+        })
+        .collect();
+    let unanswered = threads
+        .iter()
+        .filter(|t| !t.comment.answered && !t.comment.removed)
+        .count();
+    let priority = threads.iter().filter(|t| t.priority).count();
+    if filter.priority {
+        threads.retain(|t| t.priority);
+    }
+    context.insert("threads", &threads);
+    context.insert("unanswered", &unanswered);
+    context.insert("priority", &priority);
+    context.insert("priority_only", &filter.priority);
+    context.insert(
+        "mention_name",
+        &normalize_mention(user_name.unwrap_or_default()),
+    );
     let html = TEMPLATES.render("article.html", &context)?;
     Ok(Html(html).into_response())
 }
@@ -48,7 +93,7 @@ pub async fn fetch_articles(
     let mut context = Context::new();
     user_state.insert_context(&mut context);
 
-    let user_id = match user_state.user_id.clone() {
+    let user_id = match user_state.user_id {
         Some(id) => id,
         None => {
             let html = TEMPLATES.render("base.html", &context)?;
@@ -65,19 +110,84 @@ pub async fn fetch_articles(
 
     let client = get_wa_client_builder(&user_info.api_key).build()?;
     // TODO: Cooldown on re-fetching articles
-    let articles = world_list_articles(&client, &world.worldanvil_id).await?;
-    let mut urls = Vec::new();
-    let mut titles = Vec::new();
-    let mut wa_ids = Vec::new();
-    articles.into_iter().for_each(|a| {
-        urls.push(a.url);
-        titles.push(a.title);
-        wa_ids.push(a.id);
-    });
-    register_articles(user_id, world.id, &urls, &titles, &wa_ids, &pool).await?;
+    let (pages, complete) = discover_pages(&client, &world).await?;
+    register_pages(user_id, world.id, pages, complete, &pool).await?;
     Ok(Redirect::to(&format!("/world/{world_id}/")).into_response())
 }
 
+/// Find every page of a world that readers can comment on.
+/// Also returns whether every page was found, pages without a url are missing.
+async fn discover_pages(
+    client: &reqwest::Client,
+    world: &World,
+) -> anyhow::Result<(Vec<PageInsert>, bool)> {
+    let mut pages = Vec::new();
+    let mut complete = true;
+    match &world.url {
+        Some(url) => pages.push(PageInsert {
+            kind: PageKind::World,
+            worldanvil_id: world.worldanvil_id.clone(),
+            title: world.name.clone(),
+            url: url.clone(),
+            updated_at: None,
+        }),
+        None => {
+            log::info!(
+                "World {} has no homepage url yet, refresh the worlds",
+                world.id
+            );
+            complete = false;
+        }
+    }
+    let articles = world_list_articles(client, &world.worldanvil_id).await?;
+    pages.extend(articles.into_iter().map(|article| PageInsert {
+        kind: PageKind::Article,
+        worldanvil_id: article.id,
+        title: article.title,
+        url: article.url,
+        updated_at: article.update_date.to_offset_date_time(),
+    }));
+    let entities = [
+        (
+            PageKind::Timeline,
+            world_list_timelines(client, &world.worldanvil_id).await?,
+        ),
+        (
+            PageKind::Map,
+            world_list_maps(client, &world.worldanvil_id).await?,
+        ),
+        (
+            PageKind::Manuscript,
+            world_list_manuscripts(client, &world.worldanvil_id).await?,
+        ),
+    ];
+    for (kind, entities) in entities {
+        for WorldEntity {
+            id,
+            title,
+            url,
+            update_date,
+            ..
+        } in entities
+        {
+            match url {
+                Some(url) => pages.push(PageInsert {
+                    kind,
+                    worldanvil_id: id,
+                    title,
+                    url,
+                    updated_at: update_date.and_then(|date| date.to_offset_date_time()),
+                }),
+                None => {
+                    log::warn!("The {kind} {id} of world {} has no url", world.id);
+                    complete = false;
+                }
+            }
+        }
+    }
+    Ok((pages, complete))
+}
+
 pub async fn queue_all_articles(
     State(pool): State<PgPool>,
     Path(world_id): Path<i64>,
@@ -89,13 +199,22 @@ pub async fn queue_all_articles(
         Some(id) => id,
         None => return Ok(Redirect::to("/login").into_response()),
     };
-    let article_ids = get_unqueued_article_ids(&pool, &user_id, &world_id).await?;
+    let mut article_ids = get_unqueued_article_ids(&pool, &user_id, &world_id).await?;
     let mut db_conn = pool.acquire().await?;
     let conn = db_conn.acquire().await?;
-    insert_tasks(&user_id, &article_ids, conn).await?;
-    let len = article_ids.len();
+    // Only queue as many as the user has room for, the rest can be queued once those are done.
+    let settings = get_user_queue_settings(&user_id, &QueueDefaults::from_env(), conn).await?;
+    let left_out = match settings.room() {
+        Some(room) if (room as usize) < article_ids.len() => {
+            article_ids.split_off(room as usize).len()
+        }
+        _ => 0,
+    };
+    let len = insert_tasks(&user_id, &article_ids, TaskPriority::Bulk, conn).await?;
     context.insert("world_id", &world_id);
     context.insert("count", &len);
+    context.insert("left_out", &left_out);
+    context.insert("max_pending", &settings.max_pending);
     let html = TEMPLATES.render("queue_all_articles.html", &context)?;
     Ok(Html(html).into_response())
 }
@@ -103,7 +222,7 @@ pub async fn queue_all_articles(
 /// Queue a specific article for re-indexing.
 pub async fn queue_one_article(
     State(pool): State<PgPool>,
-    Path((world_id, article_id)): Path<(i64, i64)>,
+    Path((_world_id, article_id)): Path<(i64, i64)>,
     user_state: UserState,
 ) -> Result<Response, AppError> {
     let mut context = Context::new();
@@ -117,13 +236,68 @@ pub async fn queue_one_article(
     get_article_conn(tx.acquire().await?, article_id, user_id)
         .await
         .map_err(AppError::from_sql("article", &article_id))?;
-    // Check that it isn't already queued
-    if article_is_queued(&user_id, &article_id, tx.acquire().await?).await? {
+    // An article that is already queued is moved ahead instead.
+    let queued = insert_tasks(
+        &user_id,
+        &[article_id],
+        TaskPriority::Interactive,
+        tx.acquire().await?,
+    )
+    .await?;
+    tx.commit().await?;
+    if queued == 0 {
         context.insert("already_queued", &true);
-    } else {
-        insert_tasks(&user_id, &[article_id], tx.acquire().await?).await?;
-        tx.commit().await?;
     }
     let html = TEMPLATES.render("queue_one_article.html", &context)?;
     Ok(Html(html).into_response())
 }
+
+/// Give a task that was given up on a fresh set of attempts.
+pub async fn retry_article(
+    State(pool): State<PgPool>,
+    Path((world_id, article_id)): Path<(i64, i64)>,
+    user_state: UserState,
+) -> Result<Response, AppError> {
+    let user_id = match user_state.user_id {
+        Some(id) => id,
+        None => return Ok(Redirect::to("/login").into_response()),
+    };
+    let mut conn = pool.acquire().await?;
+    if !retry_dead_task(&user_id, &article_id, &mut conn).await? {
+        log::info!("Article {article_id} of user {user_id} had no failed task to retry");
+    }
+    Ok(Redirect::to(&format!("/world/{world_id}")).into_response())
+}
+
+/// Cancel the pending check of an article.
+pub async fn cancel_article(
+    State(pool): State<PgPool>,
+    Path((world_id, article_id)): Path<(i64, i64)>,
+    user_state: UserState,
+) -> Result<Response, AppError> {
+    let user_id = match user_state.user_id {
+        Some(id) => id,
+        None => return Ok(Redirect::to("/login").into_response()),
+    };
+    let mut conn = pool.acquire().await?;
+    if !cancel_task(&user_id, &article_id, &mut conn).await? {
+        log::info!("Article {article_id} of user {user_id} had no pending task to cancel");
+    }
+    Ok(Redirect::to(&format!("/world/{world_id}")).into_response())
+}
+
+/// Cancel the pending checks of all articles in a world.
+pub async fn cancel_all_articles(
+    State(pool): State<PgPool>,
+    Path(world_id): Path<i64>,
+    user_state: UserState,
+) -> Result<Response, AppError> {
+    let user_id = match user_state.user_id {
+        Some(id) => id,
+        None => return Ok(Redirect::to("/login").into_response()),
+    };
+    let mut conn = pool.acquire().await?;
+    let cancelled = cancel_world_tasks(&user_id, &world_id, &mut conn).await?;
+    log::info!("Cancelled {cancelled} tasks in world {world_id} of user {user_id}");
+    Ok(Redirect::to(&format!("/world/{world_id}")).into_response())
+}
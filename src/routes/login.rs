@@ -7,7 +7,7 @@ use crate::worldanvil_api::get_user_identity;
 use crate::worldanvil_api::schema::IdentityResult;
 use axum::extract::State;
 use axum::response::{Html, IntoResponse, Redirect, Response};
-use axum::{debug_handler, Form};
+use axum::Form;
 use serde::Deserialize;
 use sqlx::PgPool;
 use tera::Context;
@@ -1,2 +1,3 @@
 pub mod article;
 pub mod login;
+pub mod settings;
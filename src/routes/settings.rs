@@ -0,0 +1,38 @@
+use crate::auth::UserState;
+use crate::db::user::set_user_timezone;
+use crate::err::AppError;
+use axum::extract::State;
+use axum::response::{IntoResponse, Redirect, Response};
+use axum::Form;
+use chrono_tz::Tz;
+use serde::Deserialize;
+use sqlx::PgPool;
+
+#[derive(Deserialize)]
+pub struct TimezoneForm {
+    pub timezone: String,
+}
+
+/// Set the timezone WorldAnvil shows the user's comment dates in.
+/// An empty timezone falls back to the default.
+pub async fn set_timezone(
+    State(pool): State<PgPool>,
+    user_state: UserState,
+    Form(TimezoneForm { timezone }): Form<TimezoneForm>,
+) -> Result<Response, AppError> {
+    let user_id = match user_state.user_id {
+        Some(id) => id,
+        None => return Ok(Redirect::to("/login").into_response()),
+    };
+    let timezone = timezone.trim();
+    let timezone = if timezone.is_empty() {
+        None
+    } else {
+        let tz: Tz = timezone
+            .parse()
+            .map_err(|_| AppError::BadRequest(format!("Unknown timezone '{timezone}'")))?;
+        Some(tz.name())
+    };
+    set_user_timezone(&pool, &user_id, timezone).await?;
+    Ok(Redirect::to("/").into_response())
+}
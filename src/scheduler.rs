@@ -0,0 +1,183 @@
+//! Queueing articles for a recheck by themselves, more often the more active they are.
+use crate::db::queue::{get_pending_tasks, get_recheck_candidates, insert_tasks, QueueDefaults};
+use crate::db::schema::{PendingTasks, RecheckCandidate, TaskPriority};
+use dotenv::var as envvar;
+use itertools::Itertools;
+use sqlx::PgPool;
+use std::collections::HashMap;
+use std::time::Duration;
+use time::OffsetDateTime;
+
+/// Limits on how often articles are rechecked and how much is queued for each user.
+#[derive(Clone, Copy, Debug)]
+pub struct RecheckBounds {
+    /// Even the most active article waits this long between checks.
+    pub min_interval: Duration,
+    /// Even the most dormant article is checked this often.
+    pub max_interval: Duration,
+    /// Nothing more is queued for a user who already has this many tasks waiting.
+    pub max_pending: i64,
+}
+
+impl RecheckBounds {
+    pub fn from_env() -> Self {
+        let setting = |name, default| {
+            envvar(name)
+                .ok()
+                .and_then(|value| value.parse().ok())
+                .unwrap_or(default)
+        };
+        Self {
+            min_interval: Duration::from_secs(setting("AUTO_RECHECK_MIN_INTERVAL_SECS", 3600)),
+            max_interval: Duration::from_secs(setting("AUTO_RECHECK_MAX_INTERVAL_SECS", 604800)),
+            max_pending: setting("AUTO_RECHECK_MAX_PENDING", 20) as i64,
+        }
+    }
+}
+
+/// How long to wait between checks of an article that was last active at `last_active`.
+/// That is a quarter of the time it has been quiet for, so an article commented on yesterday
+/// is checked every six hours. Articles without any known activity are checked rarely.
+pub fn recheck_interval(
+    last_active: Option<OffsetDateTime>,
+    now: OffsetDateTime,
+    bounds: &RecheckBounds,
+) -> Duration {
+    let Some(last_active) = last_active else {
+        return bounds.max_interval;
+    };
+    let quiet_for: Duration = (now - last_active).try_into().unwrap_or_default();
+    (quiet_for / 4).clamp(bounds.min_interval, bounds.max_interval)
+}
+
+/// When an article is due for a check. Articles that were never checked are due right away.
+fn due_at(
+    candidate: &RecheckCandidate,
+    now: OffsetDateTime,
+    bounds: &RecheckBounds,
+) -> OffsetDateTime {
+    match candidate.last_checked {
+        None => OffsetDateTime::UNIX_EPOCH,
+        Some(last_checked) => last_checked + recheck_interval(candidate.last_active, now, bounds),
+    }
+}
+
+/// Pick the articles to queue for each user, most overdue first, without going over
+/// the scheduler's limit of waiting tasks or the user's own, whichever is lower.
+pub fn pick_rechecks(
+    candidates: Vec<RecheckCandidate>,
+    pending: &HashMap<i64, PendingTasks>,
+    now: OffsetDateTime,
+    bounds: &RecheckBounds,
+) -> HashMap<i64, Vec<i64>> {
+    candidates
+        .into_iter()
+        .filter(|candidate| due_at(candidate, now, bounds) <= now)
+        .sorted_by_key(|candidate| due_at(candidate, now, bounds))
+        .into_group_map_by(|candidate| candidate.user_id)
+        .into_iter()
+        .filter_map(|(user_id, due)| {
+            let room = match pending.get(&user_id) {
+                Some(pending) => {
+                    let max_pending = pending.max_pending.unwrap_or(i64::MAX);
+                    bounds.max_pending.min(max_pending) - pending.pending
+                }
+                None => bounds.max_pending,
+            };
+            let picked: Vec<_> = due
+                .into_iter()
+                .take(room.max(0) as usize)
+                .map(|candidate| candidate.id)
+                .collect();
+            (!picked.is_empty()).then_some((user_id, picked))
+        })
+        .collect()
+}
+
+/// Queue every article that is due for a recheck, returning how many were queued.
+pub async fn schedule_rechecks(pool: &PgPool, bounds: &RecheckBounds) -> anyhow::Result<usize> {
+    let mut tx = pool.begin().await?;
+    let candidates = get_recheck_candidates(bounds.min_interval, &mut tx).await?;
+    let pending = get_pending_tasks(&QueueDefaults::from_env(), &mut tx).await?;
+    let picked = pick_rechecks(candidates, &pending, OffsetDateTime::now_utc(), bounds);
+    let mut queued = 0;
+    for (user_id, article_ids) in picked {
+        queued += insert_tasks(&user_id, &article_ids, TaskPriority::Scheduled, &mut tx).await?;
+    }
+    tx.commit().await?;
+    Ok(queued)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use time::macros::datetime;
+
+    const BOUNDS: RecheckBounds = RecheckBounds {
+        min_interval: Duration::from_secs(3600),
+        max_interval: Duration::from_secs(7 * 86400),
+        max_pending: 2,
+    };
+    const NOW: OffsetDateTime = datetime!(2025-06-01 12:00 UTC);
+
+    #[test]
+    fn test_recheck_interval() {
+        let hours = |hours: u64| Duration::from_secs(hours * 3600);
+        let cases = [
+            (Some(datetime!(2025-06-01 11:00 UTC)), hours(1)),
+            (Some(datetime!(2025-05-31 12:00 UTC)), hours(6)),
+            (Some(datetime!(2025-05-25 12:00 UTC)), hours(42)),
+            (Some(datetime!(2024-06-01 12:00 UTC)), hours(7 * 24)),
+            (Some(datetime!(2025-06-02 12:00 UTC)), hours(1)),
+            (None, hours(7 * 24)),
+        ];
+        for (last_active, expected) in cases {
+            assert_eq!(
+                recheck_interval(last_active, NOW, &BOUNDS),
+                expected,
+                "{last_active:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_pick_rechecks() {
+        let candidate = |id, user_id, last_checked, last_active| RecheckCandidate {
+            id,
+            user_id,
+            last_checked,
+            last_active,
+        };
+        let candidates = vec![
+            // Commented on an hour ago, checked three hours ago.
+            candidate(
+                1,
+                1,
+                Some(datetime!(2025-06-01 09:00 UTC)),
+                Some(datetime!(2025-06-01 11:00 UTC)),
+            ),
+            // Quiet for a month and checked yesterday, so not due for days.
+            candidate(
+                2,
+                1,
+                Some(datetime!(2025-05-31 12:00 UTC)),
+                Some(datetime!(2025-05-01 12:00 UTC)),
+            ),
+            candidate(3, 1, None, None),
+            candidate(4, 1, Some(datetime!(2025-05-20 12:00 UTC)), None),
+            candidate(5, 2, None, None),
+            candidate(6, 3, None, None),
+            candidate(7, 3, Some(datetime!(2025-05-20 12:00 UTC)), None),
+        ];
+        let pending = |user_id, pending, max_pending| PendingTasks {
+            user_id,
+            pending,
+            max_pending,
+        };
+        let pending = HashMap::from([(2, pending(2, 2, None)), (3, pending(3, 0, Some(1)))]);
+        let picked = pick_rechecks(candidates, &pending, NOW, &BOUNDS);
+        // User 1 only has room for the two most overdue, user 2 has no room at all
+        // and user 3 may only have one task waiting.
+        assert_eq!(picked, HashMap::from([(1, vec![3, 4]), (3, vec![6])]));
+    }
+}
@@ -0,0 +1,336 @@
+//! The workers articlewatch runs, which claim queued tasks until they are told to stop.
+use crate::article_updater::{update_task, TaskError, TaskOutcome};
+use crate::db::queue::{prune_queue_history, time_until_next_task, QueueDefaults, QUEUE_CHANNEL};
+use crate::parser::profile::CompiledProfile;
+use crate::scheduler::{schedule_rechecks, RecheckBounds};
+use sqlx::postgres::PgListener;
+use sqlx::PgPool;
+use std::cell::Cell;
+use std::future::Future;
+use std::rc::Rc;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{watch, Notify};
+use tokio::task::{JoinSet, LocalSet};
+use tokio::time::Instant;
+
+/// How many workers to run and how they behave.
+#[derive(Clone, Copy, Debug)]
+pub struct WorkerConfig {
+    pub workers: usize,
+    /// How long the workers pause once a task finds WorldAnvil down for maintenance.
+    pub maintenance_backoff: Duration,
+    /// The longest an idle worker waits before looking for work, in case a wakeup was missed.
+    pub idle_timeout: Duration,
+    /// How long tasks in flight get to finish once a shutdown is asked for.
+    pub shutdown_timeout: Duration,
+    /// How often to queue articles that are due for a recheck, if at all.
+    pub recheck_every: Option<Duration>,
+    /// How often to prune old tasks from the queue, if at all.
+    pub prune_every: Option<Duration>,
+    /// How long finished tasks are kept in the queue before they are pruned.
+    pub keep_history: Duration,
+}
+
+/// What the workers got done before they stopped.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct WorkSummary {
+    /// Tasks worked through, whether or not the article could be read.
+    pub finished: usize,
+    /// Tasks still in flight when the shutdown timeout ran out. Their changes were rolled back
+    /// and they stay queued.
+    pub abandoned: usize,
+}
+
+/// State shared between the workers, which all run on the same thread.
+#[derive(Default)]
+struct Progress {
+    finished: Cell<usize>,
+    /// Tasks that have been claimed and are being worked on.
+    in_flight: Cell<usize>,
+    /// Wakes up idle workers when there may be work.
+    wake: Notify,
+    /// No worker claims a task before then, WorldAnvil is down for maintenance.
+    paused_until: Cell<Option<Instant>>,
+}
+
+impl Progress {
+    /// How much longer the workers are paused for.
+    fn pause_left(&self) -> Duration {
+        self.paused_until.get().map_or(Duration::ZERO, |until| {
+            until.saturating_duration_since(Instant::now())
+        })
+    }
+}
+
+/// Resolves on Ctrl+C or SIGTERM.
+pub async fn shutdown_signal() {
+    use tokio::signal;
+    let ctrl_c = async {
+        signal::ctrl_c()
+            .await
+            .expect("failed to install Ctrl+C handler");
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        signal::unix::signal(signal::unix::SignalKind::terminate())
+            .expect("failed to install signal handler")
+            .recv()
+            .await;
+    };
+
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {},
+        _ = terminate => {},
+    }
+}
+
+/// Claim and work on tasks until told to stop or something goes wrong with the database.
+/// A task that has been claimed is always worked through, stopping only happens in between.
+async fn run_worker(
+    worker: usize,
+    pool: PgPool,
+    profiles: Arc<[CompiledProfile]>,
+    config: WorkerConfig,
+    progress: Rc<Progress>,
+    mut stop: watch::Receiver<bool>,
+) -> anyhow::Result<()> {
+    while !*stop.borrow() {
+        let pause = progress.pause_left();
+        if !pause.is_zero() {
+            log::debug!("Worker {worker}: Paused for {}s", pause.as_secs());
+            tokio::select! {
+                _ = tokio::time::sleep(pause) => {},
+                _ = stop.changed() => {},
+            }
+            continue;
+        }
+        // Listen for wakeups before looking for work, so none sent while looking are missed.
+        let woken = progress.wake.notified();
+        tokio::pin!(woken);
+        woken.as_mut().enable();
+        let tx = pool.begin().await?;
+        // Only a claimed task is in flight, looking for one is not.
+        let claimed = Cell::new(false);
+        let outcome = update_task(tx, &profiles, config.maintenance_backoff, || {
+            claimed.set(true);
+            progress.in_flight.set(progress.in_flight.get() + 1);
+        })
+        .await;
+        if claimed.get() {
+            progress.in_flight.set(progress.in_flight.get() - 1);
+        }
+        let wait = match outcome? {
+            TaskOutcome::NoTasks | TaskOutcome::NoUser => {
+                // Wait until someone can be worked on, or until woken up by new work.
+                let mut conn = pool.acquire().await?;
+                let wait = time_until_next_task(&QueueDefaults::from_env(), &mut conn).await?;
+                let wait = wait.map_or(config.idle_timeout, |wait| wait.min(config.idle_timeout));
+                log::debug!("Worker {worker}: Idle for up to {}ms", wait.as_millis());
+                wait
+            }
+            // Other tasks would only find WorldAnvil down as well, so every worker pauses.
+            TaskOutcome::Maintenance => {
+                let until = Instant::now() + config.maintenance_backoff;
+                progress.paused_until.set(Some(until));
+                Duration::ZERO
+            }
+            TaskOutcome::Error(task_err) => {
+                let TaskError { error, .. } = task_err;
+                log::error!("Worker {worker}: {error:?}");
+                Duration::ZERO
+            }
+            TaskOutcome::Completed => {
+                progress.finished.set(progress.finished.get() + 1);
+                Duration::ZERO
+            }
+        };
+        if !wait.is_zero() {
+            tokio::select! {
+                _ = tokio::time::sleep(wait) => {},
+                _ = woken => {},
+                _ = stop.changed() => {},
+            }
+        }
+    }
+    log::debug!("Worker {worker}: Stopped");
+    Ok(())
+}
+
+/// Wake up the workers whenever the queue is notified of new work, until told to stop.
+/// A lost connection is only logged, the workers look for work by themselves every so often.
+async fn run_listener(
+    pool: PgPool,
+    progress: Rc<Progress>,
+    idle_timeout: Duration,
+    mut stop: watch::Receiver<bool>,
+) -> anyhow::Result<()> {
+    let mut listener = PgListener::connect_with(&pool).await?;
+    listener.listen(QUEUE_CHANNEL).await?;
+    while !*stop.borrow() {
+        tokio::select! {
+            notification = listener.recv() => match notification {
+                Ok(_) => progress.wake.notify_waiters(),
+                Err(e) => {
+                    log::error!("Lost the queue notifications: {e:?}");
+                    tokio::time::sleep(idle_timeout).await;
+                }
+            },
+            _ = stop.changed() => {},
+        }
+    }
+    Ok(())
+}
+
+/// Queue articles that are due for a recheck every `every` until told to stop.
+/// Failures are only logged, the next round may well go better.
+async fn run_scheduler(
+    pool: PgPool,
+    every: Duration,
+    mut stop: watch::Receiver<bool>,
+) -> anyhow::Result<()> {
+    while !*stop.borrow() {
+        match schedule_rechecks(&pool, &RecheckBounds::from_env()).await {
+            Ok(0) => log::debug!("No articles are due for a recheck"),
+            Ok(queued) => log::info!("Queued {queued} articles for a recheck"),
+            Err(e) => log::error!("Could not schedule rechecks: {e:?}"),
+        }
+        tokio::select! {
+            _ = tokio::time::sleep(every) => {},
+            _ = stop.changed() => {},
+        }
+    }
+    Ok(())
+}
+
+/// Prune old tasks from the queue every `every` until told to stop.
+/// Failures are only logged, like for the scheduler.
+async fn run_pruner(
+    pool: PgPool,
+    every: Duration,
+    keep: Duration,
+    mut stop: watch::Receiver<bool>,
+) -> anyhow::Result<()> {
+    while !*stop.borrow() {
+        match prune_queue_history(keep, &mut *pool.acquire().await?).await {
+            Ok(0) => log::debug!("No old tasks to prune"),
+            Ok(pruned) => log::info!("Pruned {pruned} old tasks from the queue"),
+            Err(e) => log::error!("Could not prune the queue: {e:?}"),
+        }
+        tokio::select! {
+            _ = tokio::time::sleep(every) => {},
+            _ = stop.changed() => {},
+        }
+    }
+    Ok(())
+}
+
+/// Run the workers until `shutdown` resolves, then give the tasks in flight
+/// `config.shutdown_timeout` to finish. Whatever is still running after that is dropped,
+/// which rolls back its transaction and leaves the task queued for next time.
+pub async fn run_workers(
+    pool: &PgPool,
+    profiles: Arc<[CompiledProfile]>,
+    config: WorkerConfig,
+    shutdown: impl Future<Output = ()>,
+) -> anyhow::Result<WorkSummary> {
+    // All workers run on this one thread. Their futures are Send, but rustc cannot prove it
+    // for the database helpers, which are generic over the lifetime of PgAcquire, so they
+    // cannot go to tokio::spawn. The workers spend nearly all their time waiting on WorldAnvil
+    // and the database, and pages are parsed on the blocking pool.
+    let local = LocalSet::new();
+    let progress = Rc::new(Progress::default());
+    let (stop, stopped) = watch::channel(false);
+    let mut running = JoinSet::new();
+    for worker in 0..config.workers {
+        running.spawn_local_on(
+            run_worker(
+                worker,
+                pool.clone(),
+                profiles.clone(),
+                config,
+                progress.clone(),
+                stopped.clone(),
+            ),
+            &local,
+        );
+    }
+    running.spawn_local_on(
+        run_listener(
+            pool.clone(),
+            progress.clone(),
+            config.idle_timeout,
+            stopped.clone(),
+        ),
+        &local,
+    );
+    if let Some(every) = config.recheck_every {
+        running.spawn_local_on(run_scheduler(pool.clone(), every, stopped.clone()), &local);
+    }
+    if let Some(every) = config.prune_every {
+        running.spawn_local_on(
+            run_pruner(pool.clone(), every, config.keep_history, stopped.clone()),
+            &local,
+        );
+    }
+
+    let summary = local
+        .run_until(async {
+            tokio::pin!(shutdown);
+            loop {
+                tokio::select! {
+                    // Workers only stop by themselves on errors, which bring the watcher down.
+                    result = running.join_next() => match result {
+                        Some(result) => result??,
+                        None => break,
+                    },
+                    _ = &mut shutdown => {
+                        log::info!(
+                            "Shutting down, waiting up to {}s for {} tasks in flight",
+                            config.shutdown_timeout.as_secs(),
+                            progress.in_flight.get()
+                        );
+                        stop.send_replace(true);
+                        let finish = async {
+                            while let Some(result) = running.join_next().await {
+                                result??;
+                            }
+                            anyhow::Ok(())
+                        };
+                        let abandoned = match tokio::time::timeout(config.shutdown_timeout, finish)
+                            .await
+                        {
+                            Ok(result) => {
+                                result?;
+                                0
+                            }
+                            Err(_) => {
+                                let abandoned = progress.in_flight.get();
+                                running.shutdown().await;
+                                abandoned
+                            }
+                        };
+                        return anyhow::Ok(WorkSummary {
+                            finished: progress.finished.get(),
+                            abandoned,
+                        });
+                    }
+                }
+            }
+            anyhow::Ok(WorkSummary {
+                finished: progress.finished.get(),
+                abandoned: 0,
+            })
+        })
+        .await?;
+    log::info!(
+        "Workers stopped after finishing {} tasks, {} tasks in flight were rolled back",
+        summary.finished,
+        summary.abandoned
+    );
+    Ok(summary)
+}
@@ -1,27 +1,33 @@
 use crate::worldanvil_api::schema::{
-    Article, ErrorBody, IdentityBody, IdentityResult, LimitOffsetBody, State, World,
-    WorldArticlesResponse, WorldsForUserResponse,
+    Article, ErrorBody, IdentityBody, IdentityResult, LimitOffsetBody, World,
+    WorldEntitiesResponse, WorldEntity, WorldsForUserResponse,
 };
 use anyhow::Context;
 use reqwest::StatusCode;
+use serde::de::DeserializeOwned;
 
 pub mod schema;
 
 const API_BASE: &str = "https://www.worldanvil.com/api/external/boromir";
 const LIST_ARTICLES: &str = const_format::concatcp!(API_BASE, "/world/articles");
+const LIST_TIMELINES: &str = const_format::concatcp!(API_BASE, "/world/timelines");
+const LIST_MAPS: &str = const_format::concatcp!(API_BASE, "/world/maps");
+const LIST_MANUSCRIPTS: &str = const_format::concatcp!(API_BASE, "/world/manuscripts");
 const USER_IDENTITY: &str = const_format::concatcp!(API_BASE, "/identity");
 const WORLDS_FOR_USER: &str = const_format::concatcp!(API_BASE, "/user/worlds");
 
-pub async fn world_list_articles(
+/// Fetch every entity of a world list endpoint, 50 at a time.
+async fn world_list<T: DeserializeOwned>(
     client: &reqwest::Client,
+    endpoint: &str,
     world_id: &str,
-) -> anyhow::Result<Vec<Article>> {
+) -> anyhow::Result<Vec<T>> {
     let mut items = vec![];
     let mut offset = 0;
     let mut done = false;
     loop {
         let res = client
-            .post(LIST_ARTICLES)
+            .post(endpoint)
             .query(&[("id", world_id)])
             .header("Content-Type", "application/json")
             .body(serde_json::to_string(&LimitOffsetBody {
@@ -31,20 +37,14 @@ pub async fn world_list_articles(
             .send()
             .await?;
         let text = &res.text().await?;
-        let res: WorldArticlesResponse =
+        let res: WorldEntitiesResponse<T> =
             serde_json::from_str(text).context(format!("Parsing json: {text}"))?;
         // If the request returned fewer than 50 responses, this means we are at the end
         if res.entities.len() < 50 {
             done = true;
         }
         offset += 50;
-        // Filter out non-public articles
-        let new_items: Vec<_> = res
-            .entities
-            .into_iter()
-            .filter(|article| !article.is_draft)
-            .collect();
-        items.extend(new_items);
+        items.extend(res.entities);
         if done {
             break;
         }
@@ -52,6 +52,51 @@ pub async fn world_list_articles(
     Ok(items)
 }
 
+pub async fn world_list_articles(
+    client: &reqwest::Client,
+    world_id: &str,
+) -> anyhow::Result<Vec<Article>> {
+    let articles: Vec<Article> = world_list(client, LIST_ARTICLES, world_id).await?;
+    // Filter out non-public articles
+    Ok(articles
+        .into_iter()
+        .filter(|article| !article.is_draft)
+        .collect())
+}
+
+/// Drafts are not public, so nobody can comment on them.
+fn published(entities: Vec<WorldEntity>) -> Vec<WorldEntity> {
+    entities
+        .into_iter()
+        .filter(|entity| entity.is_draft != Some(true))
+        .collect()
+}
+
+pub async fn world_list_timelines(
+    client: &reqwest::Client,
+    world_id: &str,
+) -> anyhow::Result<Vec<WorldEntity>> {
+    Ok(published(
+        world_list(client, LIST_TIMELINES, world_id).await?,
+    ))
+}
+
+pub async fn world_list_maps(
+    client: &reqwest::Client,
+    world_id: &str,
+) -> anyhow::Result<Vec<WorldEntity>> {
+    Ok(published(world_list(client, LIST_MAPS, world_id).await?))
+}
+
+pub async fn world_list_manuscripts(
+    client: &reqwest::Client,
+    world_id: &str,
+) -> anyhow::Result<Vec<WorldEntity>> {
+    Ok(published(
+        world_list(client, LIST_MANUSCRIPTS, world_id).await?,
+    ))
+}
+
 pub async fn get_user_identity(client: &reqwest::Client) -> anyhow::Result<IdentityResult> {
     let res = client.get(USER_IDENTITY).send().await?;
     match res.status() {
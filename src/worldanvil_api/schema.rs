@@ -1,4 +1,7 @@
+use crate::dateutil::{assume_timezone, default_timezone};
 use serde::{Deserialize, Serialize, Serializer};
+use time::macros::format_description;
+use time::{OffsetDateTime, PrimitiveDateTime};
 
 #[derive(Serialize, Deserialize, PartialEq, Eq, Debug)]
 #[serde(rename_all = "lowercase")]
@@ -46,6 +49,17 @@ pub struct Date {
     pub timezone: String,
 }
 
+impl Date {
+    /// The moment the date refers to, or None if it is not in the format WorldAnvil uses.
+    pub fn to_offset_date_time(&self) -> Option<OffsetDateTime> {
+        let format =
+            format_description!("[year]-[month]-[day] [hour]:[minute]:[second].[subsecond]");
+        let local = PrimitiveDateTime::parse(&self.date, format).ok()?;
+        let timezone = self.timezone.parse().unwrap_or_else(|_| default_timezone());
+        Some(assume_timezone(local, timezone))
+    }
+}
+
 /// I don't care about this so let's ignore it for now
 #[derive(Serialize, Deserialize, PartialEq, Debug)]
 pub struct SubscriberGroup {}
@@ -69,10 +83,23 @@ pub struct Article {
     pub position: Option<i64>,
 }
 
+/// The fields shared by timelines, maps, manuscripts and the other world content.
+#[derive(Deserialize, PartialEq, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct WorldEntity {
+    pub id: String,
+    pub title: String,
+    pub slug: Option<String>,
+    pub state: Option<State>,
+    pub is_draft: Option<bool>,
+    pub url: Option<String>,
+    pub update_date: Option<Date>,
+}
+
 #[derive(Deserialize)]
-pub struct WorldArticlesResponse {
+pub struct WorldEntitiesResponse<T> {
     pub success: bool,
-    pub entities: Vec<Article>,
+    pub entities: Vec<T>,
 }
 
 fn serialize_i64_as_string<S: Serializer>(i: &i64, s: S) -> Result<S::Ok, S::Error> {
@@ -117,6 +144,18 @@ pub struct World {
     pub is_wip: Option<bool>,
     pub is_draft: Option<bool>,
     pub entity_class: String,
+    pub url: Option<String>,
+}
+
+impl World {
+    /// The world homepage, built from the slug if the API leaves the url out.
+    pub fn homepage(&self) -> Option<String> {
+        self.url.clone().or_else(|| {
+            self.slug
+                .as_ref()
+                .map(|slug| format!("https://www.worldanvil.com/w/{slug}"))
+        })
+    }
 }
 
 #[derive(Deserialize)]
@@ -129,6 +168,7 @@ pub struct WorldsForUserResponse {
 mod test {
     use super::*;
     use serde_json;
+    use time::macros::datetime;
 
     #[test]
     fn test_article() {
@@ -176,7 +216,45 @@ mod test {
                 subscribergroups: vec![],
                 position: None,
             }
-        )
+        );
+        assert_eq!(
+            article.update_date.to_offset_date_time(),
+            Some(datetime!(2024-09-02 09:48:03 UTC))
+        );
+    }
+
+    #[test]
+    fn test_world_entity() {
+        let json = r#"
+        {
+            "id": "0b6a5a3c-3f3e-4a52-9a1d-5e0cdb3a1f10",
+            "title": "History of Solaris",
+            "slug": "history-of-solaris",
+            "state": "public",
+            "isWip": false,
+            "isDraft": false,
+            "entityClass": "Timeline",
+            "url": "https://www.worldanvil.com/w/solaris-nnie/t/history-of-solaris"
+        }"#;
+        let entity = serde_json::from_str::<WorldEntity>(json).unwrap();
+        assert_eq!(
+            entity,
+            WorldEntity {
+                id: "0b6a5a3c-3f3e-4a52-9a1d-5e0cdb3a1f10".to_string(),
+                title: "History of Solaris".to_string(),
+                slug: Some("history-of-solaris".to_string()),
+                state: Some(State::Public),
+                is_draft: Some(false),
+                url: Some(
+                    "https://www.worldanvil.com/w/solaris-nnie/t/history-of-solaris".to_string()
+                ),
+                update_date: None,
+            }
+        );
+        // Everything but the id and title may be missing.
+        let json = r#"{"id": "a", "title": "Map of Caloris"}"#;
+        let entity = serde_json::from_str::<WorldEntity>(json).unwrap();
+        assert_eq!(entity.url, None);
     }
 
     #[test]
@@ -199,7 +277,7 @@ mod test {
   "userhash": "userhash"
 }
         "#;
-        let value: IdentityBody = serde_json::from_str(&json).unwrap();
+        let value: IdentityBody = serde_json::from_str(json).unwrap();
         assert_eq!(
             value,
             IdentityBody {
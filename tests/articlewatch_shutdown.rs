@@ -0,0 +1,113 @@
+//! articlewatch stops on SIGTERM, finishing the tasks it is working on if it can
+//! and rolling them back if they take longer than the shutdown timeout.
+#![cfg(unix)]
+use axum::response::Html;
+use axum::Router;
+use libtater::db::queue::{get_next_task, insert_tasks};
+use libtater::db::schema::TaskPriority;
+use libtater::db::test_queries::add_test_article;
+use libtater::db::user::insert_user_queue;
+use libtater::parser::profile::{default_profile, CompiledProfile};
+use libtater::worker::{run_workers, shutdown_signal, WorkSummary, WorkerConfig};
+use sqlx::{Acquire, PgPool};
+use std::fs;
+use std::process::Command;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::mpsc;
+
+/// Queue the page at `url` for a new user, returning the user's id.
+async fn queue_page(pool: &PgPool, name: &str, url: &str) -> anyhow::Result<i64> {
+    let mut conn = pool.acquire().await?;
+    let (user, _, article_id) = add_test_article(&mut *conn, name, url).await?;
+    insert_user_queue(&mut *conn, &user).await?;
+    insert_tasks(&user, &[article_id], TaskPriority::Interactive, &mut conn).await?;
+    Ok(user)
+}
+
+/// Run the workers, sending SIGTERM as soon as the page has been requested.
+async fn run_until_terminated(
+    pool: &PgPool,
+    requested: &mut mpsc::UnboundedReceiver<()>,
+    shutdown_timeout: Duration,
+) -> anyhow::Result<WorkSummary> {
+    let config = WorkerConfig {
+        workers: 2,
+        maintenance_backoff: Duration::from_secs(300),
+        idle_timeout: Duration::from_secs(30),
+        shutdown_timeout,
+        recheck_every: None,
+        prune_every: None,
+        keep_history: Duration::from_secs(86400),
+    };
+    let profiles: Arc<[CompiledProfile]> = vec![default_profile().clone()].into();
+    let terminate = async {
+        requested.recv().await;
+        Command::new("kill")
+            .args(["-TERM", &std::process::id().to_string()])
+            .status()
+            .expect("kill runs");
+    };
+    let shutdown = async {
+        tokio::join!(shutdown_signal(), terminate);
+    };
+    run_workers(pool, profiles, config, shutdown).await
+}
+
+#[sqlx::test]
+async fn test_shutdown_during_slow_fetch(pool: PgPool) -> anyhow::Result<()> {
+    // Keep SIGTERM from killing the test process even before the workers listen for it.
+    let _terminate = signal(SignalKind::terminate())?;
+
+    let page = fs::read_to_string("fixtures/example-solaris-page.htm")?;
+    let delay_ms = Arc::new(AtomicU64::new(500));
+    let (requested_tx, mut requested) = mpsc::unbounded_channel();
+    let app = Router::new().fallback({
+        let delay_ms = delay_ms.clone();
+        move || async move {
+            requested_tx.send(()).ok();
+            let delay = Duration::from_millis(delay_ms.load(Ordering::SeqCst));
+            tokio::time::sleep(delay).await;
+            Html(page)
+        }
+    });
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+    let url = format!(
+        "http://{}/w/solaris-nnie/a/chewpaper-material",
+        listener.local_addr()?
+    );
+    tokio::spawn(async move { axum::serve(listener, app).await });
+
+    // A fetch that ends within the timeout is finished before the workers stop.
+    let user_id = queue_page(&pool, "patient", &url).await?;
+    let summary = run_until_terminated(&pool, &mut requested, Duration::from_secs(10)).await?;
+    assert_eq!(
+        summary,
+        WorkSummary {
+            finished: 1,
+            abandoned: 0
+        }
+    );
+    let mut conn = pool.acquire().await?;
+    let mut tx = conn.begin().await?;
+    assert!(get_next_task(&user_id, &mut tx).await?.is_none());
+    tx.rollback().await?;
+
+    // One that does not is rolled back, leaving the task queued.
+    delay_ms.store(60_000, Ordering::SeqCst);
+    let user_id = queue_page(&pool, "impatient", &url).await?;
+    let summary = run_until_terminated(&pool, &mut requested, Duration::from_millis(500)).await?;
+    assert_eq!(
+        summary,
+        WorkSummary {
+            finished: 0,
+            abandoned: 1
+        }
+    );
+    let mut tx = conn.begin().await?;
+    let task = get_next_task(&user_id, &mut tx).await?;
+    assert_eq!(task.map(|task| task.attempts), Some(0));
+    Ok(())
+}